@@ -0,0 +1,103 @@
+use std::fmt::Display;
+
+/// Stable identifiers attached to diagnostics so they can be searched for in
+/// logs and documented individually. Codes are grouped by the stage of the
+/// interpreter that reports them:
+///
+/// - `E00xx` scanning
+/// - `E01xx` parsing
+/// - `E02xx` resolving
+/// - `E03xx` runtime
+/// - `W00xx` warnings from the lint pass
+///
+/// Once published a code must never be reused for a different error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    UnterminatedString,
+    UnexpectedCharacter,
+    InvalidEscape,
+    InvalidNumber,
+    UnterminatedComment,
+
+    ExpectedExpression,
+    UnclosedDelimiter,
+    ExpectedToken,
+    InvalidAssignmentTarget,
+    TooManyArguments,
+    NestingTooDeep,
+    UnexpectedEndOfInput,
+
+    ReadInOwnInitializer,
+    TopLevelReturn,
+    AlreadyDeclared,
+    ReturnFromInitializer,
+    ThisOutsideClass,
+    InvalidSuper,
+    InheritsFromSelf,
+
+    InvalidOperand,
+    UndefinedVariable,
+    UndefinedProperty,
+    NotCallable,
+    ArityMismatch,
+    DivideByZero,
+    NotAnInstance,
+    SuperclassNotClass,
+    Io,
+    StackOverflow,
+    StepLimit,
+
+    UnusedVariable,
+    Unreachable,
+    SelfAssignment,
+    FreshComparison,
+    Shadowing,
+}
+
+impl Code {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Code::UnterminatedString => "E0001",
+            Code::UnexpectedCharacter => "E0002",
+            Code::InvalidEscape => "E0003",
+            Code::InvalidNumber => "E0004",
+            Code::UnterminatedComment => "E0005",
+            Code::ExpectedExpression => "E0100",
+            Code::UnclosedDelimiter => "E0101",
+            Code::ExpectedToken => "E0102",
+            Code::InvalidAssignmentTarget => "E0103",
+            Code::TooManyArguments => "E0104",
+            Code::NestingTooDeep => "E0105",
+            Code::UnexpectedEndOfInput => "E0106",
+            Code::ReadInOwnInitializer => "E0200",
+            Code::TopLevelReturn => "E0201",
+            Code::AlreadyDeclared => "E0202",
+            Code::ReturnFromInitializer => "E0203",
+            Code::ThisOutsideClass => "E0204",
+            Code::InvalidSuper => "E0205",
+            Code::InheritsFromSelf => "E0206",
+            Code::InvalidOperand => "E0300",
+            Code::UndefinedVariable => "E0301",
+            Code::UndefinedProperty => "E0302",
+            Code::NotCallable => "E0303",
+            Code::ArityMismatch => "E0304",
+            Code::DivideByZero => "E0305",
+            Code::NotAnInstance => "E0306",
+            Code::SuperclassNotClass => "E0307",
+            Code::Io => "E0308",
+            Code::StackOverflow => "E0309",
+            Code::StepLimit => "E0310",
+            Code::UnusedVariable => "W0001",
+            Code::Unreachable => "W0002",
+            Code::SelfAssignment => "W0003",
+            Code::FreshComparison => "W0004",
+            Code::Shadowing => "W0005",
+        }
+    }
+}
+
+impl Display for Code {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
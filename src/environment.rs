@@ -0,0 +1,112 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::{
+    codes::Code,
+    eval::Value,
+    expr::Identifier,
+    position::{Diagnostic, WithSpan},
+};
+
+/// A single scope of variable bindings. Scopes are chained together through
+/// `enclosing`, lookups and assignments walk outwards until the global scope.
+#[derive(Default)]
+pub struct Environment<'src> {
+    values: HashMap<Identifier, Value<'src>>,
+    enclosing: Option<Rc<RefCell<Environment<'src>>>>,
+}
+
+impl<'src> Environment<'src> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new innermost scope nested inside `enclosing`.
+    pub fn with_enclosing(enclosing: Rc<RefCell<Environment<'src>>>) -> Self {
+        Self {
+            values: HashMap::new(),
+            enclosing: Some(enclosing),
+        }
+    }
+
+    /// Binds a name in this scope, silently replacing any previous binding.
+    pub fn define(&mut self, name: &str, value: Value<'src>) {
+        self.values.insert(name.to_string(), value);
+    }
+
+    pub fn get(&self, name: &WithSpan<Identifier>) -> Result<Value<'src>, Diagnostic> {
+        if let Some(value) = self.values.get(&name.value) {
+            return Ok(value.clone());
+        }
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow().get(name),
+            None => Err(undefined(name)),
+        }
+    }
+
+    pub fn assign(
+        &mut self,
+        name: &WithSpan<Identifier>,
+        value: Value<'src>,
+    ) -> Result<(), Diagnostic> {
+        if let Some(slot) = self.values.get_mut(&name.value) {
+            *slot = value;
+            return Ok(());
+        }
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign(name, value),
+            None => Err(undefined(name)),
+        }
+    }
+
+    /// Reads a binding from the scope exactly `distance` hops outwards, as
+    /// computed by the resolver.
+    pub fn get_at(
+        &self,
+        distance: usize,
+        name: &WithSpan<Identifier>,
+    ) -> Result<Value<'src>, Diagnostic> {
+        if distance == 0 {
+            return self
+                .values
+                .get(&name.value)
+                .cloned()
+                .ok_or_else(|| undefined(name));
+        }
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow().get_at(distance - 1, name),
+            None => Err(undefined(name)),
+        }
+    }
+
+    /// Assigns to a binding in the scope exactly `distance` hops outwards, as
+    /// computed by the resolver.
+    pub fn assign_at(
+        &mut self,
+        distance: usize,
+        name: &WithSpan<Identifier>,
+        value: Value<'src>,
+    ) -> Result<(), Diagnostic> {
+        if distance == 0 {
+            return match self.values.get_mut(&name.value) {
+                Some(slot) => {
+                    *slot = value;
+                    Ok(())
+                }
+                None => Err(undefined(name)),
+            };
+        }
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign_at(distance - 1, name, value),
+            None => Err(undefined(name)),
+        }
+    }
+}
+
+fn undefined(name: &WithSpan<Identifier>) -> Diagnostic {
+    Diagnostic::error(format!("Undefined variable '{}'.", name.value), name.span)
+        .with_code(Code::UndefinedVariable)
+        .with_help(format!(
+            "declare it before use with `var {} = ...;`",
+            name.value
+        ))
+}
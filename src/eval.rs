@@ -1,46 +1,393 @@
 use crate::{
-    expr::Expr,
-    lexer::Token,
-    position::{Diagnostic, Span, WithSpan},
+    codes::Code,
+    environment::Environment,
+    expr::{Expr, Identifier},
+    lexer::{self, Token},
+    position::{Diagnostic, Span, TraceFrame, WithSpan},
+    resolver::Locals,
+    stmt::{ClassDecl, FunctionDecl, Stmt},
 };
-use std::fmt::Display;
+use std::{cell::RefCell, collections::HashMap, fmt::Display, io::Write, rc::Rc};
 
-pub struct Evaluator {
-    ast: Box<WithSpan<Expr>>,
-    diagnostics: Vec<Diagnostic>,
+/// Number of frames kept in the stack trace of a runtime error unless
+/// configured otherwise.
+pub const DEFAULT_TRACE_LIMIT: usize = 20;
+
+/// Number of nested function calls allowed unless configured otherwise.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1024;
+
+/// Number of statements and expressions that may be under evaluation at once,
+/// across every call in progress. Each call can nest as deeply as the parser
+/// allows, so the call depth alone does not bound how deep the evaluator
+/// recurses. Sized to fit the stack of the interpreter thread in `main.rs`.
+const MAX_EVALUATION_DEPTH: usize = 16_384;
+
+/// Tree-walking evaluator for expressions and statements. Output produced by
+/// `print` statements is written to `out`, which lets callers capture it.
+pub struct Evaluator<'src, W: Write> {
+    out: W,
+    globals: Rc<RefCell<Environment<'src>>>,
+    environment: Rc<RefCell<Environment<'src>>>,
+    locals: Locals,
+    frames: Vec<CallFrame>,
+    trace_limit: usize,
+    max_call_depth: usize,
+    // statements executed and expressions evaluated so far, checked against
+    // the optional budget
+    steps: u64,
+    step_limit: Option<u64>,
+    // statements and expressions currently being evaluated
+    depth: usize,
+}
+
+/// A function or method call currently being executed.
+struct CallFrame {
+    function: Identifier,
+    call_span: Span,
 }
 
-pub enum Value {
+#[derive(Clone)]
+pub enum Value<'src> {
     Nil,
     Boolean(bool),
     Number(f64),
     String(String),
+    Function(Rc<Function<'src>>),
+    Class(Rc<Class<'src>>),
+    Instance(Rc<RefCell<Instance<'src>>>),
+}
+
+/// Runtime value of a function declaration. Holds on to the scope it was
+/// declared in so that captured variables stay alive and are shared between
+/// every closure referencing them.
+///
+/// A function is usually stored in the very scope it holds on to, and the
+/// two then keep each other alive. Such scopes are only freed with the
+/// evaluator, as there is no cycle collector yet.
+pub struct Function<'src> {
+    declaration: Rc<FunctionDecl<'src>>,
+    closure: Rc<RefCell<Environment<'src>>>,
+    is_initializer: bool,
+}
+
+impl<'src> Function<'src> {
+    pub fn arity(&self) -> usize {
+        self.declaration.params.len()
+    }
+
+    /// Creates a copy of this method whose closure has `this` bound to the
+    /// given instance.
+    fn bind(&self, instance: &Rc<RefCell<Instance<'src>>>) -> Function<'src> {
+        let mut environment = Environment::with_enclosing(Rc::clone(&self.closure));
+        environment.define("this", Value::Instance(Rc::clone(instance)));
+        Function {
+            declaration: Rc::clone(&self.declaration),
+            closure: Rc::new(RefCell::new(environment)),
+            is_initializer: self.is_initializer,
+        }
+    }
+}
+
+pub struct Class<'src> {
+    name: Identifier,
+    superclass: Option<Rc<Class<'src>>>,
+    methods: HashMap<Identifier, Rc<Function<'src>>>,
 }
 
-impl Evaluator {
-    pub fn new(ast: Box<WithSpan<Expr>>) -> Self {
+impl<'src> Class<'src> {
+    /// Looks a method up on this class, then up the superclass chain.
+    fn find_method(&self, name: &str) -> Option<Rc<Function<'src>>> {
+        match self.methods.get(name) {
+            Some(method) => Some(Rc::clone(method)),
+            None => self.superclass.as_ref()?.find_method(name),
+        }
+    }
+
+    /// A class takes the same arguments as its initializer, if it has one.
+    pub fn arity(&self) -> usize {
+        self.find_method("init").map_or(0, |init| init.arity())
+    }
+}
+
+pub struct Instance<'src> {
+    class: Rc<Class<'src>>,
+    fields: HashMap<Identifier, Value<'src>>,
+}
+
+/// Reasons for statement execution to stop early and unwind to a caller.
+enum Unwind<'src> {
+    Return(Value<'src>),
+    Error(Diagnostic),
+}
+
+impl From<Diagnostic> for Unwind<'_> {
+    fn from(diag: Diagnostic) -> Self {
+        Unwind::Error(diag)
+    }
+}
+
+impl<'src, W: Write> Evaluator<'src, W> {
+    pub fn new(out: W) -> Self {
+        let globals = Rc::new(RefCell::new(Environment::new()));
         Self {
-            ast,
-            diagnostics: Vec::new(),
+            out,
+            environment: Rc::clone(&globals),
+            globals,
+            locals: Locals::new(),
+            frames: Vec::new(),
+            trace_limit: DEFAULT_TRACE_LIMIT,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            steps: 0,
+            step_limit: None,
+            depth: 0,
         }
     }
 
-    pub fn evaluate(&self) -> Result<Value, Diagnostic> {
-        self.evaluate_expression(&self.ast)
+    /// Caps how many function calls may be in progress at once. Going over
+    /// the cap is reported as a stack overflow rather than exhausting the
+    /// native stack the evaluator recurses on.
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
     }
 
-    pub fn error(&self, message: &str, span: Span) -> Diagnostic {
-        Diagnostic {
-            message: message.to_string(),
-            span,
+    /// Caps the number of statements and expressions the evaluator will run
+    /// through before giving up. Unlimited by default.
+    pub fn set_step_limit(&mut self, steps: u64) {
+        self.step_limit = Some(steps);
+    }
+
+    /// Caps the number of frames in the stack trace of a runtime error. Deep
+    /// traces keep their innermost and outermost frames and drop the middle.
+    pub fn set_trace_limit(&mut self, limit: usize) {
+        self.trace_limit = limit;
+    }
+
+    /// Adds the scope distances computed by the resolver. Variables without
+    /// a recorded distance are looked up in the globals.
+    pub fn resolve(&mut self, locals: Locals) {
+        self.locals.extend(locals);
+    }
+
+    pub fn evaluate(&mut self, e: &WithSpan<Expr<'src>>) -> Result<Value<'src>, Diagnostic> {
+        self.evaluate_expression(e)
+    }
+
+    /// Executes every statement of a program in order, stopping at the first
+    /// runtime error.
+    pub fn execute(&mut self, statements: &[WithSpan<Stmt<'src>>]) -> Result<(), Diagnostic> {
+        match self.execute_statements(statements) {
+            // a top level return simply ends the program
+            Ok(()) | Err(Unwind::Return(_)) => Ok(()),
+            Err(Unwind::Error(diag)) => Err(diag),
+        }
+    }
+
+    pub fn error(&self, code: Code, message: &str, span: Span) -> Diagnostic {
+        Diagnostic::error(message, span).with_code(code)
+    }
+
+    fn execute_statements(
+        &mut self,
+        statements: &[WithSpan<Stmt<'src>>],
+    ) -> Result<(), Unwind<'src>> {
+        for statement in statements {
+            self.execute_statement(statement)?;
+        }
+        Ok(())
+    }
+
+    /// Starts evaluating a statement or expression, counting it against the
+    /// step budget and the evaluation depth. Must be paired with `leave`.
+    fn enter(&mut self, span: Span) -> Result<(), Diagnostic> {
+        self.steps += 1;
+        if let Some(limit) = self.step_limit.filter(|&limit| self.steps > limit) {
+            return Err(self
+                .error(Code::StepLimit, "Execution step limit exceeded.", span)
+                .with_help(format!("the program was stopped after {} steps", limit)));
         }
+        if self.depth >= MAX_EVALUATION_DEPTH {
+            return Err(self.error(Code::StackOverflow, "Stack overflow.", span));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    fn execute_statement(&mut self, s: &WithSpan<Stmt<'src>>) -> Result<(), Unwind<'src>> {
+        self.enter(s.span)?;
+        let result = self.execute_kind(s);
+        self.leave();
+        result
     }
 
-    pub fn diagnostics(&self) -> &[Diagnostic] {
-        &self.diagnostics
+    fn execute_kind(&mut self, s: &WithSpan<Stmt<'src>>) -> Result<(), Unwind<'src>> {
+        match &s.value {
+            Stmt::Block(statements) => {
+                let environment = Environment::with_enclosing(Rc::clone(&self.environment));
+                self.execute_block(statements, environment)
+            }
+            Stmt::Class(declaration) => Ok(self.class(declaration)?),
+            Stmt::Expression(expr) => {
+                self.evaluate_expression(expr)?;
+                Ok(())
+            }
+            Stmt::Function(declaration) => {
+                self.function(declaration);
+                Ok(())
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => self.if_statement(condition, then_branch, else_branch.as_deref()),
+            Stmt::Print(expr) => Ok(self.print(expr, s.span)?),
+            Stmt::Return(value) => self.return_statement(value.as_deref()),
+            Stmt::Var { name, initializer } => Ok(self.var(name, initializer.as_deref())?),
+            Stmt::While { condition, body } => self.while_statement(condition, body),
+        }
     }
 
-    fn evaluate_expression(&self, e: &WithSpan<Expr>) -> Result<Value, Diagnostic> {
+    fn if_statement(
+        &mut self,
+        condition: &WithSpan<Expr<'src>>,
+        then_branch: &WithSpan<Stmt<'src>>,
+        else_branch: Option<&WithSpan<Stmt<'src>>>,
+    ) -> Result<(), Unwind<'src>> {
+        let condition_value = self.evaluate_expression(condition)?;
+        if self.is_truthy(&condition_value) {
+            self.execute_statement(then_branch)
+        } else if let Some(else_branch) = else_branch {
+            self.execute_statement(else_branch)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn while_statement(
+        &mut self,
+        condition: &WithSpan<Expr<'src>>,
+        body: &WithSpan<Stmt<'src>>,
+    ) -> Result<(), Unwind<'src>> {
+        loop {
+            let condition_value = self.evaluate_expression(condition)?;
+            if !self.is_truthy(&condition_value) {
+                return Ok(());
+            }
+            self.execute_statement(body)?;
+        }
+    }
+
+    /// Executes statements inside the given scope, restoring the current scope
+    /// afterwards regardless of whether execution failed.
+    fn execute_block(
+        &mut self,
+        statements: &[WithSpan<Stmt<'src>>],
+        environment: Environment<'src>,
+    ) -> Result<(), Unwind<'src>> {
+        let previous = std::mem::replace(&mut self.environment, Rc::new(RefCell::new(environment)));
+        let result = self.execute_statements(statements);
+        self.environment = previous;
+        result
+    }
+
+    fn class(&mut self, declaration: &ClassDecl<'src>) -> Result<(), Diagnostic> {
+        let superclass = match &declaration.superclass {
+            Some(expr) => match self.evaluate_expression(expr)? {
+                Value::Class(class) => Some(class),
+                _ => {
+                    return Err(self.error(
+                        Code::SuperclassNotClass,
+                        "Superclass must be a class.",
+                        expr.span,
+                    ))
+                }
+            },
+            None => None,
+        };
+
+        let enclosing = Rc::clone(&self.environment);
+        if let Some(superclass) = &superclass {
+            let mut environment = Environment::with_enclosing(Rc::clone(&enclosing));
+            environment.define("super", Value::Class(Rc::clone(superclass)));
+            self.environment = Rc::new(RefCell::new(environment));
+        }
+
+        let methods = declaration
+            .methods
+            .iter()
+            .map(|method| {
+                let function = Function {
+                    declaration: Rc::clone(method),
+                    closure: Rc::clone(&self.environment),
+                    is_initializer: method.name.value == "init",
+                };
+                (method.name.value.clone(), Rc::new(function))
+            })
+            .collect();
+        let class = Class {
+            name: declaration.name.value.clone(),
+            superclass,
+            methods,
+        };
+
+        self.environment = enclosing;
+        self.environment
+            .borrow_mut()
+            .define(&declaration.name.value, Value::Class(Rc::new(class)));
+        Ok(())
+    }
+
+    fn function(&mut self, declaration: &Rc<FunctionDecl<'src>>) {
+        let function = Function {
+            declaration: Rc::clone(declaration),
+            closure: Rc::clone(&self.environment),
+            is_initializer: false,
+        };
+        self.environment
+            .borrow_mut()
+            .define(&declaration.name.value, Value::Function(Rc::new(function)));
+    }
+
+    fn return_statement(
+        &mut self,
+        value: Option<&WithSpan<Expr<'src>>>,
+    ) -> Result<(), Unwind<'src>> {
+        let value = match value {
+            Some(expr) => self.evaluate_expression(expr)?,
+            None => Value::Nil,
+        };
+        Err(Unwind::Return(value))
+    }
+
+    fn var(
+        &mut self,
+        name: &WithSpan<Identifier>,
+        initializer: Option<&WithSpan<Expr<'src>>>,
+    ) -> Result<(), Diagnostic> {
+        let value = match initializer {
+            Some(expr) => self.evaluate_expression(expr)?,
+            None => Value::Nil,
+        };
+        self.environment.borrow_mut().define(&name.value, value);
+        Ok(())
+    }
+
+    fn print(&mut self, e: &WithSpan<Expr<'src>>, span: Span) -> Result<(), Diagnostic> {
+        let value = self.evaluate_expression(e)?;
+        writeln!(self.out, "{}", value).map_err(|err| self.error(Code::Io, &err.to_string(), span))
+    }
+
+    fn evaluate_expression(&mut self, e: &WithSpan<Expr<'src>>) -> Result<Value<'src>, Diagnostic> {
+        self.enter(e.span)?;
+        let result = self.evaluate_kind(e);
+        self.leave();
+        result
+    }
+
+    fn evaluate_kind(&mut self, e: &WithSpan<Expr<'src>>) -> Result<Value<'src>, Diagnostic> {
         // borrow the expr so we can match against it without moving
         // or copying it.
         match &e.value {
@@ -52,18 +399,63 @@ impl Evaluator {
                 left,
                 right,
             } => self.binary(operator, left, right),
+            Expr::Logical {
+                operator,
+                left,
+                right,
+            } => self.logical(operator, left, right),
+            Expr::Call { callee, arguments } => self.call(callee, arguments, e.span),
+            Expr::Get { object, name } => self.get(object, name),
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => self.set(object, name, value),
+            Expr::Super { method } => self.super_method(method, e.span),
+            Expr::This => self.lookup_variable(&this(e.span), e.span),
+            Expr::Variable(name) => self.lookup_variable(name, e.span),
+            Expr::Assign { name, value } => self.assign(name, value, e.span),
+        }
+    }
+
+    fn lookup_variable(
+        &self,
+        name: &WithSpan<Identifier>,
+        span: Span,
+    ) -> Result<Value<'src>, Diagnostic> {
+        match self.locals.get(&span) {
+            Some(&distance) => self.environment.borrow().get_at(distance, name),
+            None => self.globals.borrow().get(name),
         }
     }
 
-    fn grouping(&self, e: &WithSpan<Expr>) -> Result<Value, Diagnostic> {
+    fn assign(
+        &mut self,
+        name: &WithSpan<Identifier>,
+        e: &WithSpan<Expr<'src>>,
+        span: Span,
+    ) -> Result<Value<'src>, Diagnostic> {
+        let value = self.evaluate_expression(e)?;
+        match self.locals.get(&span) {
+            Some(&distance) => {
+                self.environment
+                    .borrow_mut()
+                    .assign_at(distance, name, value.clone())?
+            }
+            None => self.globals.borrow_mut().assign(name, value.clone())?,
+        }
+        Ok(value)
+    }
+
+    fn grouping(&mut self, e: &WithSpan<Expr<'src>>) -> Result<Value<'src>, Diagnostic> {
         self.evaluate_expression(e)
     }
 
-    fn literal(&self, t: &WithSpan<Token>) -> Value {
+    fn literal(&self, t: &WithSpan<Token>) -> Value<'src> {
         match &t.value {
-            // maybe dont do an unwrap here genius
-            Token::Number(n) => Value::Number(n.parse::<f64>().unwrap()),
-            Token::String(s) => Value::String(s.to_string()),
+            // the scanner only produces well-formed literals
+            Token::Number(n) => Value::Number(lexer::parse_number(n).unwrap_or(f64::NAN)),
+            Token::String(s) => Value::String(lexer::unescape(s)),
             Token::True => Value::Boolean(true),
             Token::False => Value::Boolean(false),
             _ => Value::Nil,
@@ -71,22 +463,24 @@ impl Evaluator {
     }
 
     fn unary(
-        &self,
+        &mut self,
         operator: &WithSpan<Token>,
-        right: &WithSpan<Expr>,
-    ) -> Result<Value, Diagnostic> {
+        right: &WithSpan<Expr<'src>>,
+    ) -> Result<Value<'src>, Diagnostic> {
         let right_value = self.evaluate_expression(right)?;
 
         match &operator.value {
             Token::Minus => match right_value {
                 Value::Number(n) => Ok(Value::Number(-n)),
                 _ => Err(self.error(
+                    Code::InvalidOperand,
                     &format!("Operand {} must be a number.", operator.value.clone()),
                     operator.span,
                 )),
             },
             Token::Bang => Ok(Value::Boolean(!self.is_truthy(&right_value))),
             _ => Err(self.error(
+                Code::InvalidOperand,
                 &format!(
                     "Unrecognized unary operator \"{}\".",
                     operator.value.clone()
@@ -97,11 +491,11 @@ impl Evaluator {
     }
 
     fn binary(
-        &self,
+        &mut self,
         operator: &WithSpan<Token>,
-        left: &WithSpan<Expr>,
-        right: &WithSpan<Expr>,
-    ) -> Result<Value, Diagnostic> {
+        left: &WithSpan<Expr<'src>>,
+        right: &WithSpan<Expr<'src>>,
+    ) -> Result<Value<'src>, Diagnostic> {
         let left_value = self.evaluate_expression(left)?;
         let right_value = self.evaluate_expression(right)?;
 
@@ -112,54 +506,347 @@ impl Evaluator {
                 // string concatenation
                 (Value::String(l), Value::String(r)) => Ok(Value::String(l + &r)),
                 _ => Err(self.error(
+                    Code::InvalidOperand,
                     "Operands must be two numbers or two strings.",
                     Span::union(left, right),
                 )),
             },
             Token::Minus => match (left_value, right_value) {
                 (Value::Number(l), Value::Number(r)) => Ok(Value::Number(l - r)),
-                _ => Err(self.error("Operands must be numbers.", Span::union(left, right))),
+                _ => Err(self.error(
+                    Code::InvalidOperand,
+                    "Operands must be numbers.",
+                    Span::union(left, right),
+                )),
             },
             Token::Slash => match (left_value, right_value) {
                 // check for divide by zero
                 (Value::Number(l), Value::Number(r)) => match r {
-                    0.0 => Err(self.error("Divide by zero.", Span::union(left, right))),
+                    0.0 => Err(self.error(
+                        Code::DivideByZero,
+                        "Divide by zero.",
+                        Span::union(left, right),
+                    )),
                     _ => Ok(Value::Number(l / r)),
                 },
-                _ => Err(self.error("Operands must be numbers.", Span::union(left, right))),
+                _ => Err(self.error(
+                    Code::InvalidOperand,
+                    "Operands must be numbers.",
+                    Span::union(left, right),
+                )),
             },
             Token::Star => match (left_value, right_value) {
                 (Value::Number(l), Value::Number(r)) => Ok(Value::Number(l * r)),
-                _ => Err(self.error("Operands must be numbers.", Span::union(left, right))),
+                _ => Err(self.error(
+                    Code::InvalidOperand,
+                    "Operands must be numbers.",
+                    Span::union(left, right),
+                )),
             },
             // relational operators
             Token::Less => match (left_value, right_value) {
                 (Value::Number(l), Value::Number(r)) => Ok(Value::Boolean(l < r)),
-                _ => Err(self.error("Operands must be numbers.", Span::union(left, right))),
+                _ => Err(self.error(
+                    Code::InvalidOperand,
+                    "Operands must be numbers.",
+                    Span::union(left, right),
+                )),
             },
             Token::LessEqual => match (left_value, right_value) {
                 (Value::Number(l), Value::Number(r)) => Ok(Value::Boolean(l <= r)),
-                _ => Err(self.error("Operands must be numbers.", Span::union(left, right))),
+                _ => Err(self.error(
+                    Code::InvalidOperand,
+                    "Operands must be numbers.",
+                    Span::union(left, right),
+                )),
             },
             Token::Greater => match (left_value, right_value) {
                 (Value::Number(l), Value::Number(r)) => Ok(Value::Boolean(l > r)),
-                _ => Err(self.error("Operands must be numbers.", Span::union(left, right))),
+                _ => Err(self.error(
+                    Code::InvalidOperand,
+                    "Operands must be numbers.",
+                    Span::union(left, right),
+                )),
             },
             Token::GreaterEqual => match (left_value, right_value) {
                 (Value::Number(l), Value::Number(r)) => Ok(Value::Boolean(l >= r)),
-                _ => Err(self.error("Operands must be numbers.", Span::union(left, right))),
+                _ => Err(self.error(
+                    Code::InvalidOperand,
+                    "Operands must be numbers.",
+                    Span::union(left, right),
+                )),
             },
             // equality
             Token::EqualEqual => Ok(Value::Boolean(self.is_equal(&left_value, &right_value))),
             Token::BangEqual => Ok(Value::Boolean(!self.is_equal(&left_value, &right_value))),
             _ => Err(self.error(
+                Code::InvalidOperand,
                 &format!("Invalid operator \"{}\"", operator.value.clone()),
                 Span::union(left, right),
             )),
         }
     }
 
-    fn is_truthy(&self, v: &Value) -> bool {
+    fn call(
+        &mut self,
+        callee: &WithSpan<Expr<'src>>,
+        arguments: &[WithSpan<Expr<'src>>],
+        span: Span,
+    ) -> Result<Value<'src>, Diagnostic> {
+        let callee_value = self.evaluate_expression(callee)?;
+
+        let mut argument_values = Vec::with_capacity(arguments.len());
+        for argument in arguments {
+            argument_values.push(self.evaluate_expression(argument)?);
+        }
+
+        match callee_value {
+            Value::Function(function) => {
+                if argument_values.len() != function.arity() {
+                    return Err(self.error(
+                        Code::ArityMismatch,
+                        &format!(
+                            "Expected {} arguments but got {}.",
+                            function.arity(),
+                            argument_values.len()
+                        ),
+                        span,
+                    ));
+                }
+                self.call_function(&function, argument_values, span)
+            }
+            Value::Class(class) => {
+                if argument_values.len() != class.arity() {
+                    return Err(self.error(
+                        Code::ArityMismatch,
+                        &format!(
+                            "Expected {} arguments but got {}.",
+                            class.arity(),
+                            argument_values.len()
+                        ),
+                        span,
+                    ));
+                }
+                self.instantiate(&class, argument_values, span)
+            }
+            _ => Err(self.error(
+                Code::NotCallable,
+                "Can only call functions and classes.",
+                span,
+            )),
+        }
+    }
+
+    fn instantiate(
+        &mut self,
+        class: &Rc<Class<'src>>,
+        arguments: Vec<Value<'src>>,
+        span: Span,
+    ) -> Result<Value<'src>, Diagnostic> {
+        let instance = Rc::new(RefCell::new(Instance {
+            class: Rc::clone(class),
+            fields: HashMap::new(),
+        }));
+        if let Some(init) = class.find_method("init") {
+            self.call_function(&init.bind(&instance), arguments, span)?;
+        }
+        Ok(Value::Instance(instance))
+    }
+
+    /// Calls `function` inside a new call frame. Errors leaving the outermost
+    /// frame they were raised in pick up a stack trace on the way out.
+    fn call_function(
+        &mut self,
+        function: &Function<'src>,
+        arguments: Vec<Value<'src>>,
+        span: Span,
+    ) -> Result<Value<'src>, Diagnostic> {
+        if self.frames.len() >= self.max_call_depth {
+            let diag = self
+                .error(Code::StackOverflow, "Stack overflow.", span)
+                .with_help(format!(
+                    "calls can be nested at most {} deep",
+                    self.max_call_depth
+                ));
+            return Err(self.with_trace(diag));
+        }
+        self.frames.push(CallFrame {
+            function: function.declaration.name.value.clone(),
+            call_span: span,
+        });
+        let result = self
+            .execute_function(function, arguments)
+            .map_err(|diag| self.with_trace(diag));
+        self.frames.pop();
+        result
+    }
+
+    fn execute_function(
+        &mut self,
+        function: &Function<'src>,
+        arguments: Vec<Value<'src>>,
+    ) -> Result<Value<'src>, Diagnostic> {
+        let mut environment = Environment::with_enclosing(Rc::clone(&function.closure));
+        for (param, argument) in function.declaration.params.iter().zip(arguments) {
+            environment.define(&param.value, argument);
+        }
+
+        let result = self.execute_block(&function.declaration.body, environment);
+        if function.is_initializer {
+            // initializers always hand back the instance, even on a bare `return;`
+            if let Err(Unwind::Error(diag)) = result {
+                return Err(diag);
+            }
+            let this = this(function.declaration.name.span);
+            return function.closure.borrow().get_at(0, &this);
+        }
+        match result {
+            Ok(()) => Ok(Value::Nil),
+            Err(Unwind::Return(value)) => Ok(value),
+            Err(Unwind::Error(diag)) => Err(diag),
+        }
+    }
+
+    /// Attaches the current call stack to an error unless a trace was already
+    /// captured further in. The innermost frame was executing the span of the
+    /// error itself, every other frame the call into the frame inside it.
+    fn with_trace(&self, mut diag: Diagnostic) -> Diagnostic {
+        if !diag.trace.is_empty() {
+            return diag;
+        }
+        let mut span = diag.span;
+        let mut trace = Vec::with_capacity(self.frames.len() + 1);
+        for frame in self.frames.iter().rev() {
+            trace.push(TraceFrame::Call {
+                function: frame.function.clone(),
+                span,
+            });
+            span = frame.call_span;
+        }
+        trace.push(TraceFrame::Call {
+            function: "script".to_string(),
+            span,
+        });
+
+        if trace.len() > self.trace_limit {
+            let head = self.trace_limit / 2;
+            let omitted = trace.len() - self.trace_limit;
+            trace.splice(head..head + omitted, [TraceFrame::Omitted(omitted)]);
+        }
+        diag.trace = trace;
+        diag
+    }
+
+    fn get(
+        &mut self,
+        object: &WithSpan<Expr<'src>>,
+        name: &WithSpan<Identifier>,
+    ) -> Result<Value<'src>, Diagnostic> {
+        let Value::Instance(instance) = self.evaluate_expression(object)? else {
+            return Err(self.error(
+                Code::NotAnInstance,
+                "Only instances have properties.",
+                name.span,
+            ));
+        };
+        if let Some(value) = instance.borrow().fields.get(&name.value) {
+            return Ok(value.clone());
+        }
+        let method = instance.borrow().class.find_method(&name.value);
+        match method {
+            Some(method) => Ok(Value::Function(Rc::new(method.bind(&instance)))),
+            None => Err(self.error(
+                Code::UndefinedProperty,
+                &format!("Undefined property '{}'.", name.value),
+                name.span,
+            )),
+        }
+    }
+
+    /// Finds `method` on the superclass, bound to the current instance. The
+    /// instance lives in the scope just inside the one holding `super`.
+    fn super_method(
+        &mut self,
+        method: &WithSpan<Identifier>,
+        span: Span,
+    ) -> Result<Value<'src>, Diagnostic> {
+        let Some(&distance) = self.locals.get(&span) else {
+            return Err(self.error(
+                Code::InvalidSuper,
+                "Can't use 'super' outside of a class.",
+                span,
+            ));
+        };
+        let superclass = self
+            .environment
+            .borrow()
+            .get_at(distance, &WithSpan::new("super".to_string(), span))?;
+        let instance = self
+            .environment
+            .borrow()
+            .get_at(distance - 1, &this(span))?;
+
+        let (Value::Class(superclass), Value::Instance(instance)) = (superclass, instance) else {
+            return Err(self.error(
+                Code::InvalidSuper,
+                "Can't use 'super' outside of a class.",
+                span,
+            ));
+        };
+        match superclass.find_method(&method.value) {
+            Some(found) => Ok(Value::Function(Rc::new(found.bind(&instance)))),
+            None => Err(self.error(
+                Code::UndefinedProperty,
+                &format!("Undefined property '{}'.", method.value),
+                method.span,
+            )),
+        }
+    }
+
+    fn set(
+        &mut self,
+        object: &WithSpan<Expr<'src>>,
+        name: &WithSpan<Identifier>,
+        value: &WithSpan<Expr<'src>>,
+    ) -> Result<Value<'src>, Diagnostic> {
+        let Value::Instance(instance) = self.evaluate_expression(object)? else {
+            return Err(self.error(
+                Code::NotAnInstance,
+                "Only instances have fields.",
+                name.span,
+            ));
+        };
+        let value = self.evaluate_expression(value)?;
+        instance
+            .borrow_mut()
+            .fields
+            .insert(name.value.clone(), value.clone());
+        Ok(value)
+    }
+
+    /// Short-circuits, yielding whichever operand decided the result rather
+    /// than coercing it to a boolean.
+    fn logical(
+        &mut self,
+        operator: &WithSpan<Token>,
+        left: &WithSpan<Expr<'src>>,
+        right: &WithSpan<Expr<'src>>,
+    ) -> Result<Value<'src>, Diagnostic> {
+        let left_value = self.evaluate_expression(left)?;
+
+        match &operator.value {
+            Token::Or if self.is_truthy(&left_value) => Ok(left_value),
+            Token::And if !self.is_truthy(&left_value) => Ok(left_value),
+            Token::Or | Token::And => self.evaluate_expression(right),
+            _ => Err(self.error(
+                Code::InvalidOperand,
+                &format!("Invalid logical operator \"{}\"", operator.value.clone()),
+                Span::union(left, right),
+            )),
+        }
+    }
+
+    fn is_truthy(&self, v: &Value<'src>) -> bool {
         match v {
             Value::Nil => false,
             Value::Boolean(b) => *b,
@@ -167,24 +854,366 @@ impl Evaluator {
         }
     }
 
-    fn is_equal(&self, left: &Value, right: &Value) -> bool {
+    fn is_equal(&self, left: &Value<'src>, right: &Value<'src>) -> bool {
         match (left, right) {
             (Value::Nil, Value::Nil) => true,
             (Value::Boolean(l), Value::Boolean(r)) => l == r,
             (Value::Number(l), Value::Number(r)) => l == r,
             (Value::String(l), Value::String(r)) => l == r,
+            (Value::Function(l), Value::Function(r)) => Rc::ptr_eq(l, r),
+            (Value::Class(l), Value::Class(r)) => Rc::ptr_eq(l, r),
+            (Value::Instance(l), Value::Instance(r)) => Rc::ptr_eq(l, r),
             _ => false,
         }
     }
 }
 
-impl Display for Value {
+impl Display for Value<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Value::Nil => write!(f, "nil"),
             Value::Boolean(b) => write!(f, "{}", b),
             Value::Number(n) => write!(f, "{}", n),
             Value::String(s) => write!(f, "{}", s),
+            Value::Function(function) => write!(f, "<fn {}>", function.declaration.name.value),
+            Value::Class(class) => write!(f, "{}", class.name),
+            Value::Instance(instance) => write!(f, "{} instance", instance.borrow().class.name),
         }
     }
 }
+
+/// Identifier used to look up the instance bound to `this`.
+fn this(span: Span) -> WithSpan<Identifier> {
+    WithSpan::new("this".to_string(), span)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Evaluator, DEFAULT_TRACE_LIMIT};
+    use crate::{
+        lexer::Scanner,
+        parser::Parser,
+        position::{Diagnostic, LineOffsets, TraceFrame},
+        resolver::Resolver,
+        stmt,
+    };
+    use std::rc::Rc;
+
+    /// Runs a program with the given stack trace cap, returning everything it
+    /// printed or the runtime error it stopped at.
+    fn execute(source: &str, trace_limit: usize) -> Result<String, Diagnostic> {
+        execute_with(source, |evaluator| evaluator.set_trace_limit(trace_limit))
+    }
+
+    /// Runs a program on an evaluator set up by `configure`.
+    fn execute_with<F>(source: &str, configure: F) -> Result<String, Diagnostic>
+    where
+        F: FnOnce(&mut Evaluator<Vec<u8>>),
+    {
+        let tokens = Scanner::new(source).scan();
+        let mut parser = Parser::new(&tokens);
+        let program = stmt::parse(&mut parser).expect("program should parse");
+        let locals = Resolver::new()
+            .resolve(&program)
+            .expect("program should resolve");
+        let mut evaluator = Evaluator::new(Vec::new());
+        configure(&mut evaluator);
+        evaluator.resolve(locals);
+        evaluator.execute(&program)?;
+        Ok(String::from_utf8(evaluator.out).unwrap())
+    }
+
+    /// Runs a program, returning everything it printed or the message of
+    /// the runtime error it stopped at.
+    fn run(source: &str) -> Result<String, String> {
+        execute(source, DEFAULT_TRACE_LIMIT).map_err(|diag| diag.message)
+    }
+
+    /// Stack trace of the runtime error a program stops at, as the lines
+    /// that get printed for it.
+    fn trace(source: &str, trace_limit: usize) -> Vec<String> {
+        let offsets = LineOffsets::new(source);
+        let diag = execute(source, trace_limit).expect_err("program should fail");
+        diag.trace
+            .iter()
+            .map(|frame| match frame {
+                TraceFrame::Call { function, span } => {
+                    format!("[line {}] in {}", offsets.line(span.start), function)
+                }
+                TraceFrame::Omitted(count) => format!("{} omitted", count),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_print_statements_run_in_order() {
+        let output = run("print 1 + 2; \"ignored\"; print \"a\" + \"b\";").unwrap();
+
+        assert_eq!(output, "3\nab\n");
+    }
+
+    #[test]
+    fn test_unicode_names_and_strings() {
+        let output =
+            run("var café = \"crème\"; fun 挨拶(名前) { return café + 名前; } print 挨拶(\" ✓\");")
+                .unwrap();
+
+        assert_eq!(output, "crème ✓\n");
+    }
+
+    #[test]
+    fn test_variables_can_be_declared_and_assigned() {
+        let output = run("var a; print a; var b = 1; a = b = b + 1; print a; print b;").unwrap();
+
+        assert_eq!(output, "nil\n2\n2\n");
+    }
+
+    #[test]
+    fn test_blocks_shadow_and_assign_through_enclosing_scopes() {
+        let output =
+            run("var a = 1; var b = 1; { var a = 2; b = 2; print a; } print a; print b;").unwrap();
+
+        assert_eq!(output, "2\n1\n2\n");
+    }
+
+    #[test]
+    fn test_dangling_else_binds_to_nearest_if() {
+        let output = run("if (true) if (false) print 1; else print 2;").unwrap();
+
+        assert_eq!(output, "2\n");
+    }
+
+    #[test]
+    fn test_while_and_for_loops() {
+        let output = run("var i = 0; while (i < 2) { print i; i = i + 1; } \
+             for (var j = 0; j < 2; j = j + 1) print j; \
+             var k = 5; for (; k < 7;) k = k + 1; print k;")
+        .unwrap();
+
+        assert_eq!(output, "0\n1\n0\n1\n7\n");
+    }
+
+    #[test]
+    fn test_logical_operators_short_circuit_and_return_operands() {
+        let output = run(
+            "print nil or \"default\"; print 1 and 2; print false and undefined; \
+             var a = 1; true or (a = 2); print a; print 1 == 2 or 3 == 3 and \"ok\";",
+        )
+        .unwrap();
+
+        assert_eq!(output, "default\n2\nfalse\n1\nok\n");
+    }
+
+    #[test]
+    fn test_recursive_functions_return_values() {
+        let output = run(
+            "fun fibonacci(n) { if (n <= 1) return n; return fibonacci(n - 2) + fibonacci(n - 1); } \
+             print fibonacci(10); print fibonacci; fun noop() {} print noop();",
+        )
+        .unwrap();
+
+        assert_eq!(output, "55\n<fn fibonacci>\nnil\n");
+    }
+
+    #[test]
+    fn test_closures_capture_their_defining_scope() {
+        let output = run(
+            "fun makeCounter() { var i = 0; fun count() { i = i + 1; return i; } return count; } \
+             var a = makeCounter(); var b = makeCounter(); \
+             print a(); print a(); print b();",
+        )
+        .unwrap();
+
+        assert_eq!(output, "1\n2\n1\n");
+    }
+
+    #[test]
+    fn test_nested_closures_share_captured_variables() {
+        let output = run("var get; var set; \
+             fun outer() { var x = \"before\"; \
+               fun middle() { \
+                 fun getter() { return x; } fun setter(v) { x = v; } \
+                 get = getter; set = setter; } \
+               middle(); } \
+             outer(); print get(); set(\"after\"); print get();")
+        .unwrap();
+
+        assert_eq!(output, "before\nafter\n");
+    }
+
+    #[test]
+    fn test_closures_bind_to_the_variable_in_scope_at_declaration() {
+        let output = run("var a = \"global\"; \
+             { fun showA() { print a; } showA(); var a = \"block\"; showA(); }")
+        .unwrap();
+
+        assert_eq!(output, "global\nglobal\n");
+    }
+
+    #[test]
+    fn test_block_local_functions_outlive_their_block() {
+        let output = run(
+            "fun returned() { var a = \"returned\"; { fun f() { return a; } return f; } } \
+             print returned()(); \
+             class Box {} var box = Box(); \
+             { var b = \"field\"; fun f() { return b; } box.f = f; } \
+             print box.f(); \
+             var kept; fun keep(f) { kept = f; } \
+             { var c = \"argument\"; fun f() { return c; } keep(f); } \
+             print kept(); \
+             var Sub; \
+             { var d = \"super\"; class A { m() { return d; } } \
+               class B < A { m() { return super.m(); } } Sub = B; } \
+             print Sub().m();",
+        )
+        .unwrap();
+
+        assert_eq!(output, "returned\nfield\nargument\nsuper\n");
+    }
+
+    #[test]
+    fn test_functions_keep_their_declaring_scope_alive() {
+        let source = "var x = 1; fun f() { return x; }";
+        let tokens = Scanner::new(source).scan();
+        let mut parser = Parser::new(&tokens);
+        let program = stmt::parse(&mut parser).expect("program should parse");
+        let locals = Resolver::new()
+            .resolve(&program)
+            .expect("program should resolve");
+        let mut evaluator = Evaluator::new(Vec::new());
+        evaluator.resolve(locals);
+        evaluator.execute(&program).unwrap();
+        let globals = Rc::downgrade(&evaluator.globals);
+        drop(evaluator);
+
+        // leaked through `f`, see `Function`
+        assert!(globals.upgrade().is_some());
+    }
+
+    #[test]
+    fn test_classes_with_fields_methods_and_initializers() {
+        let output = run("class Person { \
+               init(name) { this.name = name; } \
+               greet() { return \"Hi, \" + this.name; } } \
+             var alice = Person(\"Alice\"); \
+             print Person; print alice; print alice.greet(); \
+             var greet = alice.greet; alice.name = \"Al\"; print greet(); \
+             print alice.init(\"Alice\") == alice;")
+        .unwrap();
+
+        assert_eq!(output, "Person\nPerson instance\nHi, Alice\nHi, Al\ntrue\n");
+    }
+
+    #[test]
+    fn test_inheritance_and_super_calls() {
+        let output = run(
+            "class A { method() { return \"A\"; } name() { return \"A.name\"; } } \
+             class B < A { method() { return \"B then \" + super.method(); } } \
+             class C < B { method() { return \"C then \" + super.method(); } } \
+             var c = C(); print c.method(); print c.name();",
+        )
+        .unwrap();
+
+        assert_eq!(output, "C then B then A\nA.name\n");
+    }
+
+    #[test]
+    fn test_inheriting_from_non_class_is_runtime_error() {
+        let error = run("var NotAClass = 1; class A < NotAClass {}").unwrap_err();
+
+        assert_eq!(error, "Superclass must be a class.");
+    }
+
+    #[test]
+    fn test_property_errors() {
+        let undefined = run("class A {} A().missing;").unwrap_err();
+        let not_instance = run("var a = 1; a.field = 2;").unwrap_err();
+        let arity = run("class A { init(a) {} } A();").unwrap_err();
+
+        assert_eq!(undefined, "Undefined property 'missing'.");
+        assert_eq!(not_instance, "Only instances have fields.");
+        assert_eq!(arity, "Expected 1 arguments but got 0.");
+    }
+
+    #[test]
+    fn test_calls_check_arity_and_callee() {
+        let arity = run("fun f(a, b) {} f(1);").unwrap_err();
+        let callee = run("\"not a function\"();").unwrap_err();
+
+        assert_eq!(arity, "Expected 2 arguments but got 1.");
+        assert_eq!(callee, "Can only call functions and classes.");
+    }
+
+    #[test]
+    fn test_undefined_variable_is_runtime_error() {
+        let error = run("var a = 1; a = b;").unwrap_err();
+
+        assert_eq!(error, "Undefined variable 'b'.");
+    }
+
+    #[test]
+    fn test_runtime_errors_in_calls_report_a_stack_trace() {
+        let source = "fun inner(a) {\n\
+                        return -a;\n\
+                      }\n\
+                      class Outer {\n\
+                        call() { return inner(\"x\"); }\n\
+                      }\n\
+                      Outer().call();\n";
+
+        assert_eq!(
+            trace(source, DEFAULT_TRACE_LIMIT),
+            vec![
+                "[line 2] in inner",
+                "[line 5] in call",
+                "[line 7] in script"
+            ]
+        );
+        assert!(trace("-nil;", DEFAULT_TRACE_LIMIT).is_empty());
+    }
+
+    #[test]
+    fn test_deep_stack_traces_are_capped() {
+        let source = "fun down(n) {\n\
+                        if (n == 0) return -nil;\n\
+                        return down(n - 1);\n\
+                      }\n\
+                      down(10);\n";
+
+        assert_eq!(
+            trace(source, 4),
+            vec![
+                "[line 2] in down",
+                "[line 3] in down",
+                "8 omitted",
+                "[line 3] in down",
+                "[line 5] in script"
+            ]
+        );
+        assert_eq!(trace(source, 100).len(), 12);
+    }
+
+    #[test]
+    fn test_call_depth_is_limited() {
+        let source = "fun down(n) { if (n > 0) down(n - 1); } down(50); print \"ok\";";
+
+        let shallow = execute_with(source, |evaluator| evaluator.set_max_call_depth(51));
+        let deep = execute_with(source, |evaluator| evaluator.set_max_call_depth(50));
+
+        assert_eq!(shallow.unwrap(), "ok\n");
+        let error = deep.unwrap_err();
+        assert_eq!(error.message, "Stack overflow.");
+        assert_eq!(error.trace.len(), DEFAULT_TRACE_LIMIT + 1);
+    }
+
+    #[test]
+    fn test_step_limit_stops_runaway_programs() {
+        let error = execute_with("print 1; while (true) {}", |evaluator| {
+            evaluator.set_step_limit(1000)
+        })
+        .unwrap_err();
+
+        assert_eq!(error.message, "Execution step limit exceeded.");
+        assert!(execute_with("print 1;", |evaluator| evaluator.set_step_limit(2)).is_ok());
+    }
+}
@@ -1,28 +1,58 @@
 use std::fmt::Display;
 
 use crate::{
+    codes::Code,
     lexer::Token,
     parser::Parser,
-    position::{Span, WithSpan},
+    position::{Diagnostic, Span, WithSpan},
 };
 
+pub type Identifier = String;
+
 #[allow(dead_code)]
 #[derive(Debug)]
-pub enum Expr {
-    Literal(WithSpan<Token>),
+pub enum Expr<'src> {
+    Literal(WithSpan<Token<'src>>),
     Unary {
-        operator: WithSpan<Token>,
-        right: Box<WithSpan<Expr>>,
+        operator: WithSpan<Token<'src>>,
+        right: Box<WithSpan<Expr<'src>>>,
     },
     Binary {
-        operator: WithSpan<Token>,
-        left: Box<WithSpan<Expr>>,
-        right: Box<WithSpan<Expr>>,
+        operator: WithSpan<Token<'src>>,
+        left: Box<WithSpan<Expr<'src>>>,
+        right: Box<WithSpan<Expr<'src>>>,
+    },
+    Grouping(Box<WithSpan<Expr<'src>>>),
+    Call {
+        callee: Box<WithSpan<Expr<'src>>>,
+        arguments: Vec<WithSpan<Expr<'src>>>,
+    },
+    Get {
+        object: Box<WithSpan<Expr<'src>>>,
+        name: WithSpan<Identifier>,
+    },
+    Set {
+        object: Box<WithSpan<Expr<'src>>>,
+        name: WithSpan<Identifier>,
+        value: Box<WithSpan<Expr<'src>>>,
+    },
+    Super {
+        method: WithSpan<Identifier>,
+    },
+    This,
+    Logical {
+        operator: WithSpan<Token<'src>>,
+        left: Box<WithSpan<Expr<'src>>>,
+        right: Box<WithSpan<Expr<'src>>>,
+    },
+    Variable(WithSpan<Identifier>),
+    Assign {
+        name: WithSpan<Identifier>,
+        value: Box<WithSpan<Expr<'src>>>,
     },
-    Grouping(Box<WithSpan<Expr>>),
 }
 
-impl Display for WithSpan<Expr> {
+impl Display for WithSpan<Expr<'_>> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match &self.value {
             Expr::Literal(token) => match token.value {
@@ -36,102 +66,214 @@ impl Display for WithSpan<Expr> {
                 operator,
                 left,
                 right,
+            }
+            | Expr::Logical {
+                operator,
+                left,
+                right,
             } => {
                 write!(f, "({} {} {})", operator.value.lexeme(), left, right)
             }
             Expr::Grouping(g) => write!(f, "(group {})", g),
+            Expr::Call { callee, arguments } => {
+                write!(f, "(call {}", callee)?;
+                for argument in arguments {
+                    write!(f, " {}", argument)?;
+                }
+                write!(f, ")")
+            }
+            Expr::Get { object, name } => write!(f, "(. {} {})", object, name.value),
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => write!(f, "(= (. {} {}) {})", object, name.value, value),
+            Expr::Super { method } => write!(f, "(. super {})", method.value),
+            Expr::This => write!(f, "this"),
+            Expr::Variable(name) => write!(f, "{}", name.value),
+            Expr::Assign { name, value } => write!(f, "(= {} {})", name.value, value),
         }
     }
 }
 
-pub fn parse(parser: &mut Parser) -> Result<WithSpan<Expr>, ()> {
+pub fn parse<'a>(parser: &mut Parser<'a>) -> Result<WithSpan<Expr<'a>>, ()> {
     expression(parser)
 }
 
-fn expression(parser: &mut Parser) -> Result<WithSpan<Expr>, ()> {
-    equality(parser)
+fn expression<'a>(parser: &mut Parser<'a>) -> Result<WithSpan<Expr<'a>>, ()> {
+    parser.nested(assignment)
 }
 
-fn equality(parser: &mut Parser) -> Result<WithSpan<Expr>, ()> {
-    let mut expr = comparison(parser)?;
-    while matches!(parser.peek().unwrap(), Token::BangEqual | Token::EqualEqual) {
-        // criminal behaviour again --^
-        let operator = parser.advance();
-        let right = comparison(parser)?;
-        let span = Span::union(&expr, &right);
-        expr = WithSpan::new(
-            Expr::Binary {
-                operator,
-                left: Box::new(expr),
-                right: Box::new(right),
-            },
-            span,
-        );
+fn assignment<'a>(parser: &mut Parser<'a>) -> Result<WithSpan<Expr<'a>>, ()> {
+    let expr = or(parser)?;
+    if matches!(parser.peek(), Token::Equal) {
+        let equal = parser.advance();
+        // recurse rather than loop, assignment is right-associative
+        let value = parser.nested(assignment)?;
+        let span = Span::union(&expr, &value);
+        return match expr.value {
+            Expr::Variable(name) => Ok(WithSpan::new(
+                Expr::Assign {
+                    name,
+                    value: Box::new(value),
+                },
+                span,
+            )),
+            Expr::Get { object, name } => Ok(WithSpan::new(
+                Expr::Set {
+                    object,
+                    name,
+                    value: Box::new(value),
+                },
+                span,
+            )),
+            _ => {
+                parser.report(
+                    Diagnostic::error("Invalid assignment target.", equal.span)
+                        .with_code(Code::InvalidAssignmentTarget)
+                        .with_label(expr.span, "cannot be assigned to")
+                        .with_help("only variables and fields can be assigned to"),
+                );
+                Err(())
+            }
+        };
     }
     Ok(expr)
 }
 
-fn comparison(parser: &mut Parser) -> Result<WithSpan<Expr>, ()> {
-    let mut expr = term(parser)?;
-    while matches!(
-        parser.peek().unwrap(),
-        Token::Greater | Token::GreaterEqual | Token::Less | Token::LessEqual
-    ) {
-        let operator = parser.advance();
-        let right = term(parser)?;
-        let span = Span::union(&expr, &right);
-        expr = WithSpan::new(
-            Expr::Binary {
-                operator,
-                left: Box::new(expr),
-                right: Box::new(right),
-            },
-            span,
-        );
-    }
-    Ok(expr)
+fn or<'a>(parser: &mut Parser<'a>) -> Result<WithSpan<Expr<'a>>, ()> {
+    parser.chain(|parser| {
+        let mut expr = and(parser)?;
+        while matches!(parser.peek(), Token::Or) {
+            parser.link()?;
+            let operator = parser.advance();
+            let right = and(parser)?;
+            let span = Span::union(&expr, &right);
+            expr = WithSpan::new(
+                Expr::Logical {
+                    operator,
+                    left: Box::new(expr),
+                    right: Box::new(right),
+                },
+                span,
+            );
+        }
+        Ok(expr)
+    })
 }
 
-fn term(parser: &mut Parser) -> Result<WithSpan<Expr>, ()> {
-    let mut expr = factor(parser)?;
-    while matches!(parser.peek().unwrap(), Token::Plus | Token::Minus) {
-        let operator = parser.advance();
-        let right = factor(parser)?;
-        let span = Span::union(&expr, &right);
-        expr = WithSpan::new(
-            Expr::Binary {
-                operator,
-                left: Box::new(expr),
-                right: Box::new(right),
-            },
-            span,
-        );
-    }
-    Ok(expr)
+fn and<'a>(parser: &mut Parser<'a>) -> Result<WithSpan<Expr<'a>>, ()> {
+    parser.chain(|parser| {
+        let mut expr = equality(parser)?;
+        while matches!(parser.peek(), Token::And) {
+            parser.link()?;
+            let operator = parser.advance();
+            let right = equality(parser)?;
+            let span = Span::union(&expr, &right);
+            expr = WithSpan::new(
+                Expr::Logical {
+                    operator,
+                    left: Box::new(expr),
+                    right: Box::new(right),
+                },
+                span,
+            );
+        }
+        Ok(expr)
+    })
 }
 
-fn factor(parser: &mut Parser) -> Result<WithSpan<Expr>, ()> {
-    let mut expr = unary(parser)?;
-    while matches!(parser.peek().unwrap(), Token::Slash | Token::Star) {
-        let operator = parser.advance();
-        let right = unary(parser)?;
-        let span = Span::union(&expr, &right);
-        expr = WithSpan::new(
-            Expr::Binary {
-                operator,
-                left: Box::new(expr),
-                right: Box::new(right),
-            },
-            span,
-        );
-    }
-    Ok(expr)
+fn equality<'a>(parser: &mut Parser<'a>) -> Result<WithSpan<Expr<'a>>, ()> {
+    parser.chain(|parser| {
+        let mut expr = comparison(parser)?;
+        while matches!(parser.peek(), Token::BangEqual | Token::EqualEqual) {
+            parser.link()?;
+            let operator = parser.advance();
+            let right = comparison(parser)?;
+            let span = Span::union(&expr, &right);
+            expr = WithSpan::new(
+                Expr::Binary {
+                    operator,
+                    left: Box::new(expr),
+                    right: Box::new(right),
+                },
+                span,
+            );
+        }
+        Ok(expr)
+    })
+}
+
+fn comparison<'a>(parser: &mut Parser<'a>) -> Result<WithSpan<Expr<'a>>, ()> {
+    parser.chain(|parser| {
+        let mut expr = term(parser)?;
+        while matches!(
+            parser.peek(),
+            Token::Greater | Token::GreaterEqual | Token::Less | Token::LessEqual
+        ) {
+            parser.link()?;
+            let operator = parser.advance();
+            let right = term(parser)?;
+            let span = Span::union(&expr, &right);
+            expr = WithSpan::new(
+                Expr::Binary {
+                    operator,
+                    left: Box::new(expr),
+                    right: Box::new(right),
+                },
+                span,
+            );
+        }
+        Ok(expr)
+    })
+}
+
+fn term<'a>(parser: &mut Parser<'a>) -> Result<WithSpan<Expr<'a>>, ()> {
+    parser.chain(|parser| {
+        let mut expr = factor(parser)?;
+        while matches!(parser.peek(), Token::Plus | Token::Minus) {
+            parser.link()?;
+            let operator = parser.advance();
+            let right = factor(parser)?;
+            let span = Span::union(&expr, &right);
+            expr = WithSpan::new(
+                Expr::Binary {
+                    operator,
+                    left: Box::new(expr),
+                    right: Box::new(right),
+                },
+                span,
+            );
+        }
+        Ok(expr)
+    })
+}
+
+fn factor<'a>(parser: &mut Parser<'a>) -> Result<WithSpan<Expr<'a>>, ()> {
+    parser.chain(|parser| {
+        let mut expr = unary(parser)?;
+        while matches!(parser.peek(), Token::Slash | Token::Star) {
+            parser.link()?;
+            let operator = parser.advance();
+            let right = unary(parser)?;
+            let span = Span::union(&expr, &right);
+            expr = WithSpan::new(
+                Expr::Binary {
+                    operator,
+                    left: Box::new(expr),
+                    right: Box::new(right),
+                },
+                span,
+            );
+        }
+        Ok(expr)
+    })
 }
 
-fn unary(parser: &mut Parser) -> Result<WithSpan<Expr>, ()> {
-    if matches!(parser.peek().unwrap(), Token::Minus | Token::Bang) {
+fn unary<'a>(parser: &mut Parser<'a>) -> Result<WithSpan<Expr<'a>>, ()> {
+    if matches!(parser.peek(), Token::Minus | Token::Bang) {
         let operator = parser.advance();
-        let right = unary(parser)?;
+        let right = parser.nested(unary)?;
         let span = Span::union(&operator, &right);
         return Ok(WithSpan::new(
             Expr::Unary {
@@ -141,22 +283,106 @@ fn unary(parser: &mut Parser) -> Result<WithSpan<Expr>, ()> {
             span,
         ));
     }
-    primary(parser)
+    call(parser)
+}
+
+fn call<'a>(parser: &mut Parser<'a>) -> Result<WithSpan<Expr<'a>>, ()> {
+    parser.chain(|parser| {
+        let mut expr = primary(parser)?;
+        loop {
+            match parser.peek() {
+                Token::LeftParen => {
+                    parser.link()?;
+                    expr = finish_call(parser, expr)?;
+                }
+                Token::Dot => {
+                    parser.link()?;
+                    parser.advance();
+                    let name = parser.expect_identifier("Expected property name after '.'.")?;
+                    let span = Span::union(&expr, &name);
+                    expr = WithSpan::new(
+                        Expr::Get {
+                            object: Box::new(expr),
+                            name,
+                        },
+                        span,
+                    );
+                }
+                _ => break,
+            }
+        }
+        Ok(expr)
+    })
+}
+
+fn finish_call<'a>(
+    parser: &mut Parser<'a>,
+    callee: WithSpan<Expr<'a>>,
+) -> Result<WithSpan<Expr<'a>>, ()> {
+    parser.advance();
+    let mut arguments = Vec::new();
+    if !parser.matches(Token::RightParen) {
+        loop {
+            if arguments.len() == 255 {
+                parser.error(
+                    Code::TooManyArguments,
+                    "Can't have more than 255 arguments.",
+                    parser.current_span(),
+                );
+            }
+            arguments.push(expression(parser)?);
+            if !parser.matches(Token::Comma) {
+                break;
+            }
+            parser.advance();
+        }
+    }
+    let right_paren = parser.expect(Token::RightParen, "Expected ')' after arguments.")?;
+    let span = Span::union(&callee, &right_paren);
+    Ok(WithSpan::new(
+        Expr::Call {
+            callee: Box::new(callee),
+            arguments,
+        },
+        span,
+    ))
 }
 
-fn primary(parser: &mut Parser) -> Result<WithSpan<Expr>, ()> {
+fn primary<'a>(parser: &mut Parser<'a>) -> Result<WithSpan<Expr<'a>>, ()> {
     if matches!(
-        parser.peek().unwrap(),
+        parser.peek(),
         Token::True | Token::False | Token::Nil | Token::Number(_) | Token::String(_)
     ) {
         let token = parser.advance();
         return Ok(WithSpan::new(Expr::Literal(token.clone()), token.span));
     }
-    if matches!(parser.peek().unwrap(), Token::LeftParen) {
+    if matches!(parser.peek(), Token::Super) {
+        let keyword = parser.advance();
+        parser.expect(Token::Dot, "Expected '.' after 'super'.")?;
+        let method = parser.expect_identifier("Expected superclass method name.")?;
+        let span = Span::union(&keyword, &method);
+        return Ok(WithSpan::new(Expr::Super { method }, span));
+    }
+    if matches!(parser.peek(), Token::This) {
+        let token = parser.advance();
+        return Ok(WithSpan::new(Expr::This, token.span));
+    }
+    if let Token::Identifier(name) = parser.peek() {
+        let token = parser.advance();
+        return Ok(WithSpan::new(
+            Expr::Variable(WithSpan::new(name.to_string(), token.span)),
+            token.span,
+        ));
+    }
+    if matches!(parser.peek(), Token::LeftParen) {
         let left_paren = parser.advance();
         let expr = expression(parser)?;
         if !parser.matches(Token::RightParen) {
-            parser.error("Unmatched parentheses.", expr.span);
+            parser.report(
+                Diagnostic::error("Unmatched parentheses.", parser.current_span())
+                    .with_code(Code::UnclosedDelimiter)
+                    .with_label(left_paren.span, "unmatched `(` opened here"),
+            );
             return Err(());
         }
         let right_paren = parser.advance();
@@ -164,6 +390,10 @@ fn primary(parser: &mut Parser) -> Result<WithSpan<Expr>, ()> {
         return Ok(WithSpan::new(Expr::Grouping(Box::new(expr)), span));
     }
 
-    parser.error("Expected expression.", parser.current_span());
+    parser.error(
+        Code::ExpectedExpression,
+        "Expected expression.",
+        parser.current_span(),
+    );
     Err(())
 }
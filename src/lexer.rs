@@ -1,7 +1,12 @@
 use lazy_static::lazy_static;
 use std::{collections::HashMap, fmt::Display, iter::Peekable, str::Chars};
+use unicode_xid::UnicodeXID;
 
-use crate::position::{BytePos, Diagnostic, Span, WithSpan};
+use crate::{
+    codes::Code,
+    position::{BytePos, Diagnostic, Span, WithSpan},
+    source::SourceFile,
+};
 
 const LEFT_PAREN: char = '(';
 const RIGHT_PAREN: char = ')';
@@ -40,7 +45,7 @@ const VAR: &str = "var";
 const WHILE: &str = "while";
 
 lazy_static! {
-    static ref SINGLE_CHAR_TOKENS: HashMap<char, Token> = {
+    static ref SINGLE_CHAR_TOKENS: HashMap<char, Token<'static>> = {
         let mut m = HashMap::new();
         m.insert('(', Token::LeftParen);
         m.insert(')', Token::RightParen);
@@ -54,7 +59,7 @@ lazy_static! {
         m.insert('*', Token::Star);
         m
     };
-    static ref KEYWORDS: HashMap<&'static str, Token> = {
+    static ref KEYWORDS: HashMap<&'static str, Token<'static>> = {
         let mut m = HashMap::new();
         m.insert("and", Token::And);
         m.insert("class", Token::Class);
@@ -76,9 +81,12 @@ lazy_static! {
     };
 }
 
+/// A lexeme of lox source. Identifiers, strings and numbers borrow their
+/// text from the source rather than owning a copy, so tokens are cheap to
+/// copy around and scanning allocates nothing but the token list itself.
 #[allow(dead_code)]
-#[derive(Debug, Clone, PartialEq)]
-pub enum Token {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Token<'src> {
     LeftParen,
     RightParen,
     LeftBrace,
@@ -102,9 +110,9 @@ pub enum Token {
     LessEqual,
 
     // Identifiers
-    Identifier(String),
-    String(String),
-    Number(String),
+    Identifier(&'src str),
+    String(&'src str),
+    Number(&'src str),
 
     // Keywords,
     And,
@@ -130,6 +138,9 @@ pub enum Token {
 
 #[allow(unused)]
 pub struct Scanner<'a> {
+    source: &'a str,
+    // position of the first byte of `source`
+    base: BytePos,
     pos: BytePos,
     it: Peekable<Chars<'a>>,
     diagnostics: Vec<Diagnostic>,
@@ -139,14 +150,26 @@ pub struct Scanner<'a> {
 impl<'a> Scanner<'a> {
     pub fn new(data: &'a str) -> Self {
         Self {
+            source: data,
+            base: BytePos::default(),
             pos: BytePos::default(),
             it: data.chars().peekable(),
             diagnostics: Vec::new(),
         }
     }
 
-    pub fn scan(&mut self) -> Vec<WithSpan<Token>> {
-        let mut tokens: Vec<WithSpan<Token>> = Vec::new();
+    /// Scans a source registered with a `SourceMap`, so that tokens are
+    /// positioned within its range rather than from zero.
+    pub fn for_file(file: &'a SourceFile) -> Self {
+        Self {
+            base: file.base(),
+            pos: file.base(),
+            ..Self::new(file.source())
+        }
+    }
+
+    pub fn scan(&mut self) -> Vec<WithSpan<Token<'a>>> {
+        let mut tokens: Vec<WithSpan<Token<'a>>> = Vec::new();
 
         loop {
             let start_pos = self.pos;
@@ -182,22 +205,22 @@ impl<'a> Scanner<'a> {
         tokens
     }
 
-    fn match_token(&mut self, ch: char, start_pos: BytePos) -> Result<Option<Token>, Diagnostic> {
+    fn match_token(
+        &mut self,
+        ch: char,
+        start_pos: BytePos,
+    ) -> Result<Option<Token<'a>>, Diagnostic> {
         use Token::*;
 
         match ch {
             ' ' | '\n' | '\r' | '\t' => Ok(None),
-            '"' => {
-                let s = self.consume_while(|ch| ch != '"');
-                match self.next() {
-                    None => Err(Diagnostic::new("Unterminated String", start_pos, self.pos)),
-                    _ => Ok(Some(String(s))),
-                }
-            }
+            '"' => self.string(start_pos),
             '/' => {
                 if self.consume_if(|ch| ch == '/') {
                     self.consume_while(|ch| ch != '\n');
                     Ok(None)
+                } else if self.consume_if(|ch| ch == '*') {
+                    self.block_comment(start_pos)
                 } else {
                     Ok(Some(Slash))
                 }
@@ -206,49 +229,161 @@ impl<'a> Scanner<'a> {
             '=' => Ok(Some(self.either('=', EqualEqual, Equal))),
             '<' => Ok(Some(self.either('=', LessEqual, Less))),
             '>' => Ok(Some(self.either('=', GreaterEqual, Greater))),
-            c if c.is_numeric() => Ok(self.number(c)),
-            c if c.is_alphabetic() || c == '_' => Ok(self.identifier(c)),
+            c if c.is_ascii_digit() => self.number(start_pos),
+            c if c.is_xid_start() || c == '_' => Ok(self.identifier(start_pos)),
             _ => {
                 if let Some(tok) = SINGLE_CHAR_TOKENS.get(&ch) {
-                    Ok(Some(tok.clone()))
+                    Ok(Some(*tok))
                 } else {
                     Err(Diagnostic::new(
                         format!("Unexpected character: {}", ch),
                         start_pos,
                         self.pos,
-                    ))
+                    )
+                    .with_code(Code::UnexpectedCharacter))
+                }
+            }
+        }
+    }
+
+    /// Skips a block comment whose opening `/*` has been consumed. Block
+    /// comments nest, so each `/*` inside one needs its own `*/`.
+    fn block_comment(&mut self, start_pos: BytePos) -> Result<Option<Token<'a>>, Diagnostic> {
+        // start of every comment that is still open, innermost last
+        let mut open = vec![start_pos];
+        while let Some(&last) = open.last() {
+            let pos = self.pos;
+            match self.next() {
+                Some('/') if self.consume_if(|ch| ch == '*') => open.push(pos),
+                Some('*') if self.consume_if(|ch| ch == '/') => {
+                    open.pop();
+                }
+                Some(_) => {}
+                None => {
+                    let opening = BytePos(last.0 + 2);
+                    return Err(
+                        Diagnostic::new("Unterminated block comment.", last, opening)
+                            .with_code(Code::UnterminatedComment)
+                            .with_help("add a closing '*/', nested comments need one each"),
+                    );
                 }
             }
         }
+        Ok(None)
     }
 
-    fn identifier(&mut self, ch: char) -> Option<Token> {
-        let mut ident = String::new();
-        ident.push(ch);
-        let rest: String = self.consume_while(|c| c.is_ascii_alphanumeric() || c == '_');
-        ident.push_str(&rest);
-        let keyword = KEYWORDS.get(ident.as_str());
+    /// Scans a string literal whose opening quote has been consumed. The
+    /// token keeps the raw text between the quotes, escapes are only checked
+    /// here and decoded by [`unescape`] when the value is needed.
+    fn string(&mut self, start_pos: BytePos) -> Result<Option<Token<'a>>, Diagnostic> {
+        let contents = self.pos;
+        let mut valid = true;
+        loop {
+            match self.peek() {
+                None => {
+                    let quote = Span {
+                        start: start_pos,
+                        end: contents,
+                    };
+                    return Err(Diagnostic::new("Unterminated String", self.pos, self.pos)
+                        .with_code(Code::UnterminatedString)
+                        .with_label(quote, "string starts here")
+                        .with_help("add a closing '\"' to end the string"));
+                }
+                Some('"') => break,
+                Some('\\') => valid &= self.escape(),
+                Some(_) => {
+                    self.next();
+                }
+            }
+        }
+        let s = self.lexeme(contents);
+        // skip the closing quote
+        self.next();
+        // a string with bad escapes has already been reported
+        Ok(valid.then_some(Token::String(s)))
+    }
+
+    /// Checks the escape sequence starting at the current backslash,
+    /// reporting it if invalid.
+    fn escape(&mut self) -> bool {
+        let start = self.pos;
+        self.next(); // backslash
+        let (message, help) = match self.next() {
+            Some('n' | 't' | 'r' | '0' | '"' | '\\') => return true,
+            // the string is unterminated, which is reported on its own
+            None => return true,
+            Some('u') => {
+                if self.consume_if(|c| c == '{') {
+                    let digits = self.consume_while(|c| c.is_ascii_hexdigit());
+                    if self.consume_if(|c| c == '}') && decode_unicode(digits).is_some() {
+                        return true;
+                    }
+                }
+                (
+                    "Invalid unicode escape.".to_string(),
+                    "unicode escapes are written '\\u{XXXX}' with 1 to 6 hex digits \
+                     naming a character",
+                )
+            }
+            Some(c) => (
+                format!("Invalid escape sequence: \\{}", c),
+                "valid escapes are '\\n', '\\t', '\\r', '\\0', '\\\"', '\\\\' and '\\u{XXXX}'",
+            ),
+        };
+        self.error(
+            Diagnostic::new(message, start, self.pos)
+                .with_code(Code::InvalidEscape)
+                .with_help(help),
+        );
+        false
+    }
+
+    /// Scans an identifier or keyword. Identifiers are made of Unicode
+    /// `XID_Start` and `XID_Continue` characters like Rust's, and may also
+    /// start with `_`.
+    fn identifier(&mut self, start_pos: BytePos) -> Option<Token<'a>> {
+        self.consume_while(|c| c.is_xid_continue());
+        let ident = self.lexeme(start_pos);
+        let keyword = KEYWORDS.get(ident);
         match keyword {
-            Some(kw) => Some(kw.clone()),
+            Some(kw) => Some(*kw),
             None => Some(Token::Identifier(ident)),
         }
     }
 
-    fn number(&mut self, ch: char) -> Option<Token> {
-        let mut number = String::new();
-        number.push(ch);
-        let pre_decimal: String = self.consume_while(|c| c.is_numeric());
-        number.push_str(&pre_decimal);
+    /// Scans a numeric literal. Everything that could belong to it is taken
+    /// in, including stray letters, so that malformed literals are reported
+    /// whole rather than split into a number and an identifier.
+    fn number(&mut self, start_pos: BytePos) -> Result<Option<Token<'a>>, Diagnostic> {
+        let word = |c: char| c.is_xid_continue();
+        let mut text = self.consume_while(word);
+        let decimal = radix(text).is_none();
+
+        if decimal && self.peek() == Some(&'.') && self.consume_if_next(|c| c.is_ascii_digit()) {
+            text = self.consume_while(word);
+        }
+        // the sign of an exponent, as in `1e-9`
+        if decimal
+            && text.ends_with(['e', 'E'])
+            && matches!(self.peek(), Some('+' | '-'))
+            && self.consume_if_next(|c| c.is_ascii_digit())
+        {
+            text = self.consume_while(word);
+        }
 
-        if self.peek() == Some(&'.') && self.consume_if_next(|c| ch.is_numeric()) {
-            let post_decimal: String = self.consume_while(|c| c.is_numeric());
-            number.push('.');
-            number.push_str(&post_decimal);
+        let literal = self.lexeme(start_pos);
+        match parse_number(literal) {
+            Ok(_) => Ok(Some(Token::Number(literal))),
+            Err(help) => Err(
+                Diagnostic::new("Invalid number literal.", start_pos, self.pos)
+                    .with_code(Code::InvalidNumber)
+                    .with_help(help),
+            ),
         }
-        Some(Token::Number(number))
     }
 
-    fn either(&mut self, to_match: char, matched: Token, unmatched: Token) -> Token {
+    fn either(&mut self, to_match: char, matched: Token<'a>, unmatched: Token<'a>) -> Token<'a> {
         if self.consume_if(|ch| ch == to_match) {
             matched
         } else {
@@ -295,21 +430,27 @@ impl<'a> Scanner<'a> {
         }
     }
 
-    /// Consume characters until reaching some condition.
-    fn consume_while<CharMatchFn>(&mut self, matches: CharMatchFn) -> String
+    /// Consume characters until reaching some condition, returning the
+    /// consumed part of the source.
+    fn consume_while<CharMatchFn>(&mut self, matches: CharMatchFn) -> &'a str
     where
         CharMatchFn: Fn(char) -> bool,
     {
-        let mut chars = String::new();
+        let start = self.pos;
         while let Some(&ch) = self.peek() {
             if matches(ch) {
                 self.next().unwrap(); // safe, we peeked some
-                chars.push(ch);
             } else {
                 break;
             }
         }
-        chars
+        self.lexeme(start)
+    }
+
+    /// Source text from `start` up to the current position.
+    fn lexeme(&self, start: BytePos) -> &'a str {
+        let source = self.source;
+        &source[(start.0 - self.base.0) as usize..(self.pos.0 - self.base.0) as usize]
     }
 
     fn next(&mut self) -> Option<char> {
@@ -338,7 +479,122 @@ impl<'a> Scanner<'a> {
     }
 }
 
-impl Token {
+/// Radix and digits of a hex, octal or binary literal, or `None` for decimal
+/// ones.
+fn radix(literal: &str) -> Option<(u32, &str)> {
+    let digits = literal.get(2..)?;
+    match &literal[..2] {
+        "0x" | "0X" => Some((16, digits)),
+        "0o" | "0O" => Some((8, digits)),
+        "0b" | "0B" => Some((2, digits)),
+        _ => None,
+    }
+}
+
+/// Checks a run of digits in the given radix, which may be separated by
+/// single underscores.
+fn check_digits(digits: &str, radix: u32, what: &str) -> Result<(), String> {
+    if digits.is_empty() {
+        return Err(format!("expected {} digits", what));
+    }
+    if digits.starts_with('_') || digits.ends_with('_') || digits.contains("__") {
+        return Err("'_' can only be used between two digits".to_string());
+    }
+    match digits.chars().find(|&c| c != '_' && !c.is_digit(radix)) {
+        Some(c) => Err(format!("'{}' is not a valid {} digit", c, what)),
+        None => Ok(()),
+    }
+}
+
+/// Value of a numeric literal such as `1_000`, `2.5e-3`, `0xFF`, `0o17` or
+/// `0b1010`, or the help to show for a malformed one.
+pub fn parse_number(literal: &str) -> Result<f64, String> {
+    if let Some((radix, digits)) = radix(literal) {
+        let what = match radix {
+            16 => "hex",
+            8 => "octal",
+            _ => "binary",
+        };
+        check_digits(digits, radix, what)?;
+        // folded into a float rather than an integer so that long literals
+        // lose precision instead of overflowing
+        return Ok(digits
+            .chars()
+            .filter_map(|c| c.to_digit(radix))
+            .fold(0.0, |value, digit| value * radix as f64 + digit as f64));
+    }
+
+    let (mantissa, exponent) = match literal.find(['e', 'E']) {
+        Some(at) => (&literal[..at], Some(&literal[at + 1..])),
+        None => (literal, None),
+    };
+    let (integer, fraction) = match mantissa.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (mantissa, None),
+    };
+    check_digits(integer, 10, "decimal")?;
+    if let Some(fraction) = fraction {
+        check_digits(fraction, 10, "decimal")?;
+    }
+    if let Some(exponent) = exponent {
+        let digits = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        check_digits(digits, 10, "exponent")?;
+    }
+    literal
+        .replace('_', "")
+        .parse()
+        .map_err(|_| "not a valid number".to_string())
+}
+
+/// Character named by the hex digits of a `\\u{XXXX}` escape.
+fn decode_unicode(digits: &str) -> Option<char> {
+    if digits.is_empty() || digits.len() > 6 {
+        return None;
+    }
+    char::from_u32(u32::from_str_radix(digits, 16).ok()?)
+}
+
+/// Decodes the escape sequences in the raw text of a string literal. Escapes
+/// the scanner would have rejected are kept as written.
+pub fn unescape(raw: &str) -> String {
+    let mut out = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        let rest = chars.as_str();
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('0') => out.push('\0'),
+            Some(c @ ('"' | '\\')) => out.push(c),
+            Some('u') => {
+                let decoded = rest
+                    .strip_prefix("u{")
+                    .and_then(|rest| rest.split_once('}'))
+                    .and_then(|(digits, after)| Some((decode_unicode(digits)?, after)));
+                match decoded {
+                    Some((decoded, after)) => {
+                        out.push(decoded);
+                        chars = after.chars();
+                    }
+                    None => out.push_str("\\u"),
+                }
+            }
+            Some(c) => {
+                out.push('\\');
+                out.push(c);
+            }
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+impl Token<'_> {
     fn token_type(&self) -> String {
         match self {
             Token::LeftParen => "LEFT_PAREN".to_string(),
@@ -429,26 +685,29 @@ impl Token {
 
     pub fn literal(&self) -> String {
         match self {
-            Token::String(string) => string.to_string(),
-            Token::Number(number) => format!("{:?}", number.parse::<f64>().unwrap()),
+            Token::String(string) => unescape(string),
+            Token::Number(number) => match parse_number(number) {
+                Ok(value) => format!("{:?}", value),
+                Err(_) => "null".to_string(),
+            },
             _ => "null".to_string(),
         }
     }
 }
 
-impl From<&WithSpan<Token>> for Token {
-    fn from(with_span: &WithSpan<Token>) -> Self {
-        with_span.value.clone()
+impl<'src> From<&WithSpan<Token<'src>>> for Token<'src> {
+    fn from(with_span: &WithSpan<Token<'src>>) -> Self {
+        with_span.value
     }
 }
 
-impl From<WithSpan<Token>> for Token {
-    fn from(with_span: WithSpan<Token>) -> Self {
+impl<'src> From<WithSpan<Token<'src>>> for Token<'src> {
+    fn from(with_span: WithSpan<Token<'src>>) -> Self {
         with_span.value
     }
 }
 
-impl Display for Token {
+impl Display for Token<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
@@ -459,3 +718,311 @@ impl Display for Token {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{hint::black_box, time::Instant};
+
+    use super::{Scanner, Token};
+    use crate::{
+        codes::Code,
+        parser::Parser,
+        position::{BytePos, Span},
+        source::SourceMap,
+        stmt,
+    };
+
+    fn span(start: u32, end: u32) -> Span {
+        Span {
+            start: BytePos(start),
+            end: BytePos(end),
+        }
+    }
+
+    #[test]
+    fn test_unicode_identifiers() {
+        let source = "var café = _ünïcödé1 + 変数 + Δx;";
+        let mut scanner = Scanner::new(source);
+        let tokens: Vec<_> = scanner.scan().into_iter().map(|t| t.value).collect();
+
+        assert!(!scanner.has_errors());
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Var,
+                Token::Identifier("café"),
+                Token::Equal,
+                Token::Identifier("_ünïcödé1"),
+                Token::Plus,
+                Token::Identifier("変数"),
+                Token::Plus,
+                Token::Identifier("Δx"),
+                Token::Semicolon,
+                Token::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn test_only_ascii_digits_start_numbers() {
+        let mut sources = SourceMap::new();
+        let file = sources.add("test.lox", "print ü + ٣;\nx٣ 1é");
+        let mut scanner = Scanner::for_file(sources.file(file));
+        let tokens: Vec<_> = scanner.scan().into_iter().map(|t| t.value).collect();
+
+        let errors: Vec<_> = scanner
+            .diagnostics()
+            .iter()
+            .map(|diag| {
+                let location = sources.file(file).location(diag.span.start);
+                (diag.message.as_str(), location)
+            })
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                ("Unexpected character: ٣", (1, 11)),
+                ("Invalid number literal.", (2, 4))
+            ]
+        );
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Print,
+                Token::Identifier("ü"),
+                Token::Plus,
+                Token::Semicolon,
+                Token::Identifier("x٣"),
+                Token::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn test_block_comments_nest() {
+        let source = "1 /* a /* b\n*/ c */ 2\n/**/3 /* / * */ 4";
+        let mut scanner = Scanner::new(source);
+        let tokens: Vec<_> = scanner.scan().into_iter().map(|t| t.value).collect();
+
+        assert!(!scanner.has_errors());
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Number("1"),
+                Token::Number("2"),
+                Token::Number("3"),
+                Token::Number("4"),
+                Token::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unterminated_block_comment_points_at_its_opening() {
+        let mut scanner = Scanner::new("1 /* outer\n/* inner */\n");
+        scanner.scan();
+
+        let diag = &scanner.diagnostics()[0];
+        assert_eq!(diag.message, "Unterminated block comment.");
+        assert_eq!(diag.code, Some(Code::UnterminatedComment));
+        assert_eq!(diag.span, span(2, 4));
+    }
+
+    #[test]
+    fn test_lines_after_block_comments() {
+        let mut sources = SourceMap::new();
+        let file = sources.add("test.lox", "/* one\ntwo\n/* three\n*/ */\nprint $;");
+        let mut scanner = Scanner::for_file(sources.file(file));
+        scanner.scan();
+
+        let diag = &scanner.diagnostics()[0];
+        assert_eq!(diag.message, "Unexpected character: $");
+        assert_eq!(sources.file(file).location(diag.span.start), (5, 7));
+    }
+
+    #[test]
+    fn test_number_literals() {
+        let source = "1_000_000 2.5e-3 1E9 0xFF 0o17 0b1010 0Xdead_BEEF 3.25";
+        let mut scanner = Scanner::new(source);
+        let literals: Vec<_> = scanner
+            .scan()
+            .iter()
+            .map(|token| token.value.literal())
+            .collect();
+
+        assert!(!scanner.has_errors());
+        assert_eq!(
+            literals,
+            vec![
+                "1000000.0",
+                "0.0025",
+                "1000000000.0",
+                "255.0",
+                "15.0",
+                "10.0",
+                "3735928559.0",
+                "3.25",
+                "null"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_malformed_numbers_are_reported_whole() {
+        let mut scanner = Scanner::new("0x 1e 1__0 0b102 12ab 1_ 1e+ 1.5.2");
+        let tokens = scanner.scan();
+
+        let errors: Vec<_> = scanner
+            .diagnostics()
+            .iter()
+            .map(|diag| (diag.span, diag.help.clone().unwrap()))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (span(0, 2), "expected hex digits".to_string()),
+                (span(3, 5), "expected exponent digits".to_string()),
+                (
+                    span(6, 10),
+                    "'_' can only be used between two digits".to_string()
+                ),
+                (span(11, 16), "'2' is not a valid binary digit".to_string()),
+                (span(17, 21), "'a' is not a valid decimal digit".to_string()),
+                (
+                    span(22, 24),
+                    "'_' can only be used between two digits".to_string()
+                ),
+                (span(25, 27), "expected exponent digits".to_string()),
+            ]
+        );
+        // `1e+` leaves its sign behind, `1.5.2` is a number, a dot and a number
+        let tokens: Vec<_> = tokens.into_iter().map(|token| token.value).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Plus,
+                Token::Number("1.5"),
+                Token::Dot,
+                Token::Number("2"),
+                Token::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn test_string_escapes_are_decoded() {
+        let source = r#""tab\t quote\" slash\\ \r\n \0 \u{48}\u{1F600}""#;
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan();
+
+        assert!(!scanner.has_errors());
+        assert_eq!(tokens[0].value, Token::String(&source[1..source.len() - 1]));
+        assert_eq!(
+            tokens[0].value.literal(),
+            "tab\t quote\" slash\\ \r\n \0 H\u{1F600}"
+        );
+    }
+
+    #[test]
+    fn test_invalid_escapes_are_reported_at_the_backslash() {
+        let mut scanner = Scanner::new(r#"print "a\qb \u{110000} \u12";"#);
+        let tokens = scanner.scan();
+
+        let errors: Vec<_> = scanner
+            .diagnostics()
+            .iter()
+            .map(|diag| (diag.message.as_str(), diag.span, diag.code))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    "Invalid escape sequence: \\q",
+                    span(8, 10),
+                    Some(Code::InvalidEscape)
+                ),
+                (
+                    "Invalid unicode escape.",
+                    span(12, 22),
+                    Some(Code::InvalidEscape)
+                ),
+                (
+                    "Invalid unicode escape.",
+                    span(23, 25),
+                    Some(Code::InvalidEscape)
+                ),
+            ]
+        );
+        // scanning carries on after the string
+        let tokens: Vec<_> = tokens.into_iter().map(|token| token.value).collect();
+        assert_eq!(tokens, vec![Token::Print, Token::Semicolon, Token::Eof]);
+    }
+
+    #[test]
+    fn test_unterminated_string_labels_the_opening_quote() {
+        let mut scanner = Scanner::new("var s = \"one\ntwo \\\"\n");
+        scanner.scan();
+
+        let diag = &scanner.diagnostics()[0];
+        assert_eq!(diag.code, Some(Code::UnterminatedString));
+        assert_eq!(diag.span, span(20, 20));
+        assert_eq!(diag.labels[0].span, span(8, 9));
+        assert_eq!(scanner.diagnostics().len(), 1);
+    }
+
+    /// Lox program of at least `size` bytes, mixing every kind of token.
+    fn generate(size: usize) -> String {
+        let mut source = String::with_capacity(size + 256);
+        let mut i = 0;
+        while source.len() < size {
+            source.push_str(&format!(
+                "// function number {i}\n\
+                 fun compute_{i}(first, second) {{\n\
+                 \x20 var total = first * {i}.5 + second / 3;\n\
+                 \x20 if (total >= 100 and !(second == nil)) {{\n\
+                 \x20   print \"large result from compute_{i}\";\n\
+                 \x20 }}\n\
+                 \x20 return total - {i};\n\
+                 }}\n"
+            ));
+            i += 1;
+        }
+        source
+    }
+
+    /// Runs `pass` over the source a few times and prints its throughput.
+    fn measure<F: Fn(&str) -> usize>(name: &str, source: &str, pass: F) {
+        let runs = 10;
+        let start = Instant::now();
+        let mut count = 0;
+        for _ in 0..runs {
+            count = black_box(pass(source));
+        }
+        let elapsed = start.elapsed();
+
+        let megabytes = source.len() as f64 / (1024.0 * 1024.0);
+        println!(
+            "{}: {} items from {:.1} MB in {:.2?} per run, {:.1} MB/s",
+            name,
+            count,
+            megabytes,
+            elapsed / runs,
+            megabytes * runs as f64 / elapsed.as_secs_f64()
+        );
+    }
+
+    /// Measures scanning and parsing throughput. Run it in release mode with
+    /// `cargo test --release bench_scan -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_scan() {
+        let source = generate(4 * 1024 * 1024);
+
+        measure("scan", &source, |source| Scanner::new(source).scan().len());
+        measure("scan + parse", &source, |source| {
+            let tokens = Scanner::new(source).scan();
+            let mut parser = Parser::new(&tokens);
+            stmt::parse(&mut parser).map_or(0, |program| program.len())
+        });
+    }
+}
@@ -0,0 +1,524 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    codes::Code,
+    expr::{Expr, Identifier},
+    lexer::Token,
+    position::{Diagnostic, Span, WithSpan},
+    stmt::{ClassDecl, FunctionDecl, Stmt},
+};
+
+/// Individually switchable warnings reported by the [`Linter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    UnusedVariable,
+    UnreachableCode,
+    SelfAssignment,
+    FreshComparison,
+    Shadowing,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 5] = [
+        Lint::UnusedVariable,
+        Lint::UnreachableCode,
+        Lint::SelfAssignment,
+        Lint::FreshComparison,
+        Lint::Shadowing,
+    ];
+
+    /// Name the lint is switched on and off by on the command line.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::UnusedVariable => "unused-variable",
+            Lint::UnreachableCode => "unreachable-code",
+            Lint::SelfAssignment => "self-assignment",
+            Lint::FreshComparison => "fresh-comparison",
+            Lint::Shadowing => "shadowing",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|lint| lint.name() == name)
+    }
+
+    pub fn code(&self) -> Code {
+        match self {
+            Lint::UnusedVariable => Code::UnusedVariable,
+            Lint::UnreachableCode => Code::Unreachable,
+            Lint::SelfAssignment => Code::SelfAssignment,
+            Lint::FreshComparison => Code::FreshComparison,
+            Lint::Shadowing => Code::Shadowing,
+        }
+    }
+}
+
+/// Set of lints the [`Linter`] reports. Shadowing is deliberate often enough
+/// that it has to be asked for, every other lint is on by default.
+#[derive(Debug, Clone)]
+pub struct Lints {
+    enabled: HashSet<Lint>,
+}
+
+impl Default for Lints {
+    fn default() -> Self {
+        Self {
+            enabled: Lint::ALL
+                .into_iter()
+                .filter(|&lint| lint != Lint::Shadowing)
+                .collect(),
+        }
+    }
+}
+
+impl Lints {
+    pub fn enable(&mut self, lint: Lint) {
+        self.enabled.insert(lint);
+    }
+
+    pub fn disable(&mut self, lint: Lint) {
+        self.enabled.remove(&lint);
+    }
+
+    pub fn is_enabled(&self, lint: Lint) -> bool {
+        self.enabled.contains(&lint)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BindingKind {
+    Variable,
+    Parameter,
+    Function,
+    Class,
+}
+
+/// A name declared in some scope.
+struct Binding {
+    span: Span,
+    kind: BindingKind,
+    // whether the value of the binding is ever read
+    used: bool,
+}
+
+/// Static pass run after the resolver has accepted a program. Walks the AST
+/// the same way the resolver does, reporting code that is valid but most
+/// likely a mistake as warnings.
+pub struct Linter {
+    lints: Lints,
+    // unlike the resolver, the outermost scope holds the globals so that
+    // global classes can be recognised
+    scopes: Vec<HashMap<Identifier, Binding>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Linter {
+    pub fn new(lints: Lints) -> Self {
+        Self {
+            lints,
+            scopes: vec![HashMap::new()],
+            diagnostics: Vec::new(),
+        }
+    }
+
+    pub fn lint(&mut self, statements: &[WithSpan<Stmt>]) {
+        self.lint_statements(statements);
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    fn warn(&mut self, lint: Lint, diagnostic: Diagnostic) {
+        if self.lints.is_enabled(lint) {
+            self.diagnostics.push(diagnostic.with_code(lint.code()));
+        }
+    }
+
+    fn lint_statements(&mut self, statements: &[WithSpan<Stmt>]) {
+        for statement in statements {
+            self.lint_statement(statement);
+        }
+
+        let returning = statements.iter().position(always_returns);
+        if let Some(index) = returning {
+            if let (Some(first), Some(last)) = (statements.get(index + 1), statements.last()) {
+                self.warn(
+                    Lint::UnreachableCode,
+                    Diagnostic::warning("Unreachable code.", Span::union(first, last)).with_label(
+                        statements[index].span,
+                        "any code following this statement is unreachable",
+                    ),
+                );
+            }
+        }
+    }
+
+    fn lint_statement(&mut self, s: &WithSpan<Stmt>) {
+        match &s.value {
+            Stmt::Block(statements) => {
+                self.begin_scope();
+                self.lint_statements(statements);
+                self.end_scope();
+            }
+            Stmt::Class(declaration) => self.lint_class(declaration),
+            Stmt::Expression(expr) | Stmt::Print(expr) => self.lint_expression(expr),
+            Stmt::Function(declaration) => {
+                self.declare(&declaration.name, BindingKind::Function);
+                self.lint_function(declaration);
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.lint_expression(condition);
+                self.lint_statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.lint_statement(else_branch);
+                }
+            }
+            Stmt::Return(value) => {
+                if let Some(value) = value {
+                    self.lint_expression(value);
+                }
+            }
+            Stmt::Var { name, initializer } => {
+                if let Some(initializer) = initializer {
+                    self.lint_expression(initializer);
+                }
+                self.declare(name, BindingKind::Variable);
+            }
+            Stmt::While { condition, body } => {
+                self.lint_expression(condition);
+                self.lint_statement(body);
+            }
+        }
+    }
+
+    fn lint_class(&mut self, declaration: &ClassDecl) {
+        self.declare(&declaration.name, BindingKind::Class);
+        if let Some(superclass) = &declaration.superclass {
+            self.lint_expression(superclass);
+        }
+        for method in &declaration.methods {
+            self.lint_function(method);
+        }
+    }
+
+    fn lint_function(&mut self, declaration: &FunctionDecl) {
+        self.begin_scope();
+        for param in &declaration.params {
+            self.declare(param, BindingKind::Parameter);
+        }
+        self.lint_statements(&declaration.body);
+        self.end_scope();
+    }
+
+    fn lint_expression(&mut self, e: &WithSpan<Expr>) {
+        match &e.value {
+            Expr::Literal(_) | Expr::Super { .. } | Expr::This => {}
+            Expr::Unary { right, .. } => self.lint_expression(right),
+            Expr::Binary {
+                operator,
+                left,
+                right,
+            } => {
+                self.lint_comparison(operator, left, right);
+                self.lint_expression(left);
+                self.lint_expression(right);
+            }
+            Expr::Logical { left, right, .. } => {
+                self.lint_expression(left);
+                self.lint_expression(right);
+            }
+            Expr::Grouping(expr) => self.lint_expression(expr),
+            Expr::Call { callee, arguments } => {
+                self.lint_expression(callee);
+                for argument in arguments {
+                    self.lint_expression(argument);
+                }
+            }
+            Expr::Get { object, .. } => self.lint_expression(object),
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                if let Expr::Get {
+                    object: source,
+                    name: field,
+                } = &value.value
+                {
+                    if field.value == name.value && same_place(&object.value, &source.value) {
+                        self.warn(
+                            Lint::SelfAssignment,
+                            Diagnostic::warning(
+                                format!("Field '{}' is assigned to itself.", name.value),
+                                e.span,
+                            ),
+                        );
+                    }
+                }
+                self.lint_expression(value);
+                self.lint_expression(object);
+            }
+            Expr::Variable(name) => {
+                if let Some(binding) = self.lookup_mut(&name.value) {
+                    binding.used = true;
+                }
+            }
+            Expr::Assign { name, value } => {
+                if let Expr::Variable(source) = &value.value {
+                    if source.value == name.value {
+                        self.warn(
+                            Lint::SelfAssignment,
+                            Diagnostic::warning(
+                                format!("Variable '{}' is assigned to itself.", name.value),
+                                e.span,
+                            ),
+                        );
+                    }
+                }
+                // assigning to a variable does not count as using it
+                self.lint_expression(value);
+            }
+        }
+    }
+
+    /// Warns about `==` and `!=` against an instance built in the comparison
+    /// itself. Instances are only equal to themselves, so the result is known
+    /// before the program runs.
+    fn lint_comparison(
+        &mut self,
+        operator: &WithSpan<Token>,
+        left: &WithSpan<Expr>,
+        right: &WithSpan<Expr>,
+    ) {
+        let result = match operator.value {
+            Token::EqualEqual => "false",
+            Token::BangEqual => "true",
+            _ => return,
+        };
+        for operand in [left, right] {
+            if self.is_construction(&operand.value) {
+                self.warn(
+                    Lint::FreshComparison,
+                    Diagnostic::warning(
+                        format!(
+                            "Comparison against a newly constructed instance is always {}.",
+                            result
+                        ),
+                        Span::union(left, right),
+                    )
+                    .with_label(operand.span, "new instance created here")
+                    .with_help("instances are only equal to themselves, compare their fields"),
+                );
+                return;
+            }
+        }
+    }
+
+    fn is_construction(&self, e: &Expr) -> bool {
+        match e {
+            Expr::Grouping(expr) => self.is_construction(&expr.value),
+            Expr::Call { callee, .. } => match &callee.value {
+                Expr::Variable(name) => self
+                    .lookup(&name.value)
+                    .is_some_and(|binding| binding.kind == BindingKind::Class),
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Binding> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn lookup_mut(&mut self, name: &str) -> Option<&mut Binding> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    /// Closes a local scope, warning about every binding in it that was never
+    /// read. Names starting with an underscore are meant to go unused.
+    fn end_scope(&mut self) {
+        let Some(scope) = self.scopes.pop() else {
+            return;
+        };
+        let mut unused: Vec<(Identifier, Binding)> = scope
+            .into_iter()
+            .filter(|(name, binding)| !binding.used && !name.starts_with('_'))
+            .collect();
+        unused.sort_by_key(|(_, binding)| binding.span.start);
+
+        for (name, binding) in unused {
+            let kind = match binding.kind {
+                BindingKind::Variable => "variable",
+                BindingKind::Parameter => "parameter",
+                BindingKind::Function => "function",
+                BindingKind::Class => "class",
+            };
+            self.warn(
+                Lint::UnusedVariable,
+                Diagnostic::warning(format!("Unused {} '{}'.", kind, name), binding.span)
+                    .with_help(format!("if this is intentional, rename it to '_{}'", name)),
+            );
+        }
+    }
+
+    fn declare(&mut self, name: &WithSpan<Identifier>, kind: BindingKind) {
+        if self.scopes.len() > 1 {
+            // globals are left out, shadowing them is the only way to get a
+            // local with the same name
+            let shadowed = self.scopes[1..self.scopes.len() - 1]
+                .iter()
+                .rev()
+                .find_map(|scope| scope.get(&name.value))
+                .map(|binding| binding.span);
+            if let Some(shadowed) = shadowed {
+                self.warn(
+                    Lint::Shadowing,
+                    Diagnostic::warning(
+                        format!("Declaration of '{}' shadows an outer local.", name.value),
+                        name.span,
+                    )
+                    .with_label(shadowed, "shadowed declaration here"),
+                );
+            }
+        }
+
+        let scope = self
+            .scopes
+            .last_mut()
+            .expect("global scope is never popped");
+        scope.insert(
+            name.value.clone(),
+            Binding {
+                span: name.span,
+                kind,
+                used: false,
+            },
+        );
+    }
+}
+
+/// Whether executing the statement always ends in a `return`.
+fn always_returns(s: &WithSpan<Stmt>) -> bool {
+    match &s.value {
+        Stmt::Return(_) => true,
+        Stmt::Block(statements) => statements.iter().any(always_returns),
+        Stmt::If {
+            then_branch,
+            else_branch: Some(else_branch),
+            ..
+        } => always_returns(then_branch) && always_returns(else_branch),
+        _ => false,
+    }
+}
+
+/// Whether two expressions always refer to the same variable or instance.
+fn same_place(a: &Expr, b: &Expr) -> bool {
+    match (a, b) {
+        (Expr::Variable(a), Expr::Variable(b)) => a.value == b.value,
+        (Expr::This, Expr::This) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Lint, Linter, Lints};
+    use crate::{lexer::Scanner, parser::Parser, stmt};
+
+    fn warnings_with(source: &str, lints: Lints) -> Vec<String> {
+        let tokens = Scanner::new(source).scan();
+        let mut parser = Parser::new(&tokens);
+        let program = stmt::parse(&mut parser).expect("program should parse");
+        let mut linter = Linter::new(lints);
+        linter.lint(&program);
+        linter
+            .diagnostics()
+            .iter()
+            .map(|diag| format!("{}: {}", diag.code.unwrap(), diag.message))
+            .collect()
+    }
+
+    fn warnings(source: &str) -> Vec<String> {
+        warnings_with(source, Lints::default())
+    }
+
+    #[test]
+    fn test_reports_unused_locals_and_parameters() {
+        assert_eq!(
+            warnings("var global; fun f(a, b, _c) { var d = a; var e; e = 1; { fun g() {} } } f;"),
+            vec![
+                "W0001: Unused function 'g'.",
+                "W0001: Unused parameter 'b'.",
+                "W0001: Unused variable 'd'.",
+                "W0001: Unused variable 'e'.",
+            ]
+        );
+        assert!(warnings("fun f() { var a = 1; fun g() { return a; } return g; }").is_empty());
+    }
+
+    #[test]
+    fn test_reports_code_after_return() {
+        assert_eq!(
+            warnings("fun f(a) { if (a) return 1; else { return 2; } print a; print 3; }"),
+            vec!["W0002: Unreachable code."]
+        );
+        assert!(warnings("fun f(a) { if (a) return 1; print a; }").is_empty());
+    }
+
+    #[test]
+    fn test_reports_self_assignment() {
+        assert_eq!(
+            warnings("var a = 1; a = a; class A { f() { this.x = this.x; } }"),
+            vec![
+                "W0003: Variable 'a' is assigned to itself.",
+                "W0003: Field 'x' is assigned to itself.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reports_comparison_against_new_instance() {
+        assert_eq!(
+            warnings("class A {} var a = A(); print a == A(); print (A()) != a; print a == a;"),
+            vec![
+                "W0004: Comparison against a newly constructed instance is always false.",
+                "W0004: Comparison against a newly constructed instance is always true.",
+            ]
+        );
+        assert!(warnings("fun make() {} print make() == make();").is_empty());
+    }
+
+    #[test]
+    fn test_shadowing_is_opt_in() {
+        let source = "var g; fun f(a) { print a; var g; { var a = 1; print a; } print g; } f;";
+        assert!(warnings(source).is_empty());
+
+        let mut lints = Lints::default();
+        lints.enable(Lint::Shadowing);
+        assert_eq!(
+            warnings_with(source, lints),
+            vec!["W0005: Declaration of 'a' shadows an outer local."]
+        );
+    }
+
+    #[test]
+    fn test_lints_can_be_disabled() {
+        let mut lints = Lints::default();
+        lints.disable(Lint::UnusedVariable);
+
+        assert!(warnings_with("fun f(a) { var b; }", lints).is_empty());
+    }
+}
@@ -1,137 +1,302 @@
+mod codes;
+mod environment;
 mod eval;
 mod expr;
 mod lexer;
+mod lint;
 mod parser;
 mod position;
+mod render;
+mod resolver;
+mod source;
+mod stmt;
 
-use std::{env, fs};
+use std::{env, fs, io, process::ExitCode, str::FromStr, thread};
 
 use crate::{
     eval::Evaluator,
     lexer::{Scanner, Token},
-    parser::Parser,
-    position::{LineOffsets, WithSpan},
+    lint::{Lint, Linter, Lints},
+    parser::{Parser, MAX_NESTING_LIMIT},
+    position::{Diagnostic, WithSpan},
+    render::{ErrorFormat, Renderer},
+    resolver::Resolver,
+    source::{FileId, SourceFile, SourceMap},
 };
 
-fn tokenize(filename: &str) {
-    let file_contents = fs::read_to_string(filename).unwrap_or_else(|_| {
-        eprintln!("Failed to read file {}", filename);
-        String::new()
-    });
-
-    if !file_contents.is_empty() {
-        let mut scanner = Scanner::new(&file_contents);
-        let offsets = LineOffsets::new(&file_contents);
-        let tokens: Vec<Token> = scanner
-            .scan()
-            .into_iter()
-            .map(WithSpan::into_inner)
-            .collect();
-        if scanner.has_errors() {
-            let diagnostics = scanner.diagnostics();
-            for diag in diagnostics {
-                let line = offsets.line(diag.span.end);
-                eprintln!("[line {}] Error: {}", line, &diag.message);
+// Exit statuses follow jlox, which takes them from sysexits.h.
+const EXIT_USAGE: u8 = 64;
+/// Scan, parse or resolve errors.
+const EXIT_COMPILE_ERROR: u8 = 65;
+const EXIT_NO_INPUT: u8 = 66;
+const EXIT_RUNTIME_ERROR: u8 = 70;
+
+/// Native stack given to the thread running a command. Every pass over the
+/// AST recurses on it, so it has to hold the deepest nesting and call depth
+/// the limits can be set to, even in unoptimized builds. Pages are only
+/// committed as they are touched.
+const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+/// Command line flags shared by every command.
+#[derive(Default)]
+struct Options {
+    /// Highlight diagnostics with ANSI colors.
+    color: bool,
+    /// Layout diagnostics are written to stderr in.
+    error_format: ErrorFormat,
+    /// Warnings reported before running a program.
+    lints: Lints,
+    /// Refuse to run programs with warnings.
+    deny_warnings: bool,
+    /// Cap on the number of frames in runtime stack traces.
+    trace_limit: Option<usize>,
+    /// Cap on how deeply statements and expressions may be nested.
+    max_depth: Option<usize>,
+    /// Cap on how many function calls may be in progress at once.
+    max_call_depth: Option<usize>,
+    /// Cap on the number of evaluation steps a program may take.
+    step_limit: Option<u64>,
+}
+
+/// Applies a single `--flag` or `--flag=value` argument to the options,
+/// returning the message to print for invalid ones.
+fn set_option(options: &mut Options, arg: &str) -> Result<(), String> {
+    let (flag, value) = match arg.split_once('=') {
+        Some((flag, value)) => (flag, Some(value)),
+        None => (arg, None),
+    };
+    match (flag, value) {
+        ("--color", None) => options.color = true,
+        ("--deny-warnings", None) => options.deny_warnings = true,
+        ("--warn" | "--allow", Some(name)) => {
+            let lint = Lint::parse(name).ok_or_else(|| format!("Unknown lint: {}", name))?;
+            if flag == "--warn" {
+                options.lints.enable(lint);
+            } else {
+                options.lints.disable(lint);
             }
         }
-        for token in tokens {
-            println!("{}", token);
+        ("--error-format", Some(name)) => {
+            options.error_format = ErrorFormat::parse(name)
+                .ok_or_else(|| format!("Unknown error format: {}", name))?;
         }
+        ("--max-trace-frames", Some(value)) => options.trace_limit = Some(number(value)?),
+        ("--max-nesting", Some(value)) => {
+            let depth = number(value)?;
+            if depth > MAX_NESTING_LIMIT {
+                return Err(format!(
+                    "Nesting limit too large: {} (at most {})",
+                    depth, MAX_NESTING_LIMIT
+                ));
+            }
+            options.max_depth = Some(depth);
+        }
+        ("--max-call-depth", Some(value)) => options.max_call_depth = Some(number(value)?),
+        ("--max-steps", Some(value)) => options.step_limit = Some(number(value)?),
+        _ => return Err(format!("Unknown option: {}", arg)),
     }
+    Ok(())
 }
 
-fn parse(filename: &str) {
-    let file_contents = fs::read_to_string(filename).unwrap_or_else(|_| {
-        eprintln!("Failed to read file {}", filename);
-        String::new()
-    });
-
-    if !file_contents.is_empty() {
-        let mut scanner = Scanner::new(&file_contents);
-        let offsets = LineOffsets::new(&file_contents);
-        let tokens: Vec<WithSpan<Token>> = scanner.scan().into_iter().collect();
-
-        if scanner.has_errors() {
-            let diagnostics = scanner.diagnostics();
-            for diag in diagnostics {
-                let line = offsets.line(diag.span.end);
-                eprintln!("[line {}] Error: {}", line, &diag.message);
-            }
-        } else {
-            let mut parser = Parser::new(&tokens);
-
-            match expr::parse(&mut parser) {
-                Ok(ast) => {
-                    println!("{}", ast);
-                }
-                Err(_) => {
-                    for diag in parser.diagnostics() {
-                        let line = offsets.line(diag.span.end);
-                        eprintln!("[line {}] Error: {}", line, &diag.message);
-                    }
-                }
-            }
+fn number<T: FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid number: {}", value))
+}
+
+fn report(renderer: &Renderer, diagnostics: &[Diagnostic]) {
+    for diag in diagnostics {
+        eprint!("{}", renderer.render(diag));
+    }
+}
+
+/// Scans the whole source, reporting any errors. Tokens are only handed
+/// back if the source scanned cleanly.
+fn scan<'a>(file: &'a SourceFile, renderer: &Renderer) -> Option<Vec<WithSpan<Token<'a>>>> {
+    let mut scanner = Scanner::for_file(file);
+    let tokens = scanner.scan();
+    if scanner.has_errors() {
+        report(renderer, scanner.diagnostics());
+        return None;
+    }
+    Some(tokens)
+}
+
+fn parser<'a>(tokens: &'a [WithSpan<Token>], options: &Options) -> Parser<'a> {
+    let mut parser = Parser::new(tokens);
+    if let Some(depth) = options.max_depth {
+        parser.set_max_depth(depth);
+    }
+    parser
+}
+
+fn evaluator<'src>(options: &Options) -> Evaluator<'src, io::Stdout> {
+    let mut evaluator = Evaluator::new(io::stdout());
+    if let Some(limit) = options.trace_limit {
+        evaluator.set_trace_limit(limit);
+    }
+    if let Some(depth) = options.max_call_depth {
+        evaluator.set_max_call_depth(depth);
+    }
+    if let Some(steps) = options.step_limit {
+        evaluator.set_step_limit(steps);
+    }
+    evaluator
+}
+
+fn tokenize(sources: &SourceMap, file: FileId, options: &Options) -> ExitCode {
+    let renderer = Renderer::new(sources, options.color).with_format(options.error_format);
+    let mut scanner = Scanner::for_file(sources.file(file));
+    let tokens: Vec<Token> = scanner
+        .scan()
+        .into_iter()
+        .map(WithSpan::into_inner)
+        .collect();
+    report(&renderer, scanner.diagnostics());
+    // valid tokens are still printed when part of the input failed to scan
+    for token in tokens {
+        println!("{}", token);
+    }
+    if scanner.has_errors() {
+        return ExitCode::from(EXIT_COMPILE_ERROR);
+    }
+    ExitCode::SUCCESS
+}
+
+fn parse(sources: &SourceMap, file: FileId, options: &Options) -> ExitCode {
+    let renderer = Renderer::new(sources, options.color).with_format(options.error_format);
+    let Some(tokens) = scan(sources.file(file), &renderer) else {
+        return ExitCode::from(EXIT_COMPILE_ERROR);
+    };
+
+    let mut parser = parser(&tokens, options);
+    match expr::parse(&mut parser) {
+        Ok(ast) => {
+            println!("{}", ast);
+            ExitCode::SUCCESS
+        }
+        Err(_) => {
+            report(&renderer, parser.diagnostics());
+            ExitCode::from(EXIT_COMPILE_ERROR)
         }
     }
 }
 
-fn evaluate(filename: &str) {
-    let file_contents = fs::read_to_string(filename).unwrap_or_else(|_| {
-        eprintln!("Failed to read file {}", filename);
-        String::new()
-    });
-
-    if !file_contents.is_empty() {
-        let mut scanner = Scanner::new(&file_contents);
-        let offsets = LineOffsets::new(&file_contents);
-        let tokens: Vec<WithSpan<Token>> = scanner.scan().into_iter().collect();
-
-        if scanner.has_errors() {
-            for diag in scanner.diagnostics() {
-                let line = offsets.line(diag.span.end);
-                eprintln!("[line {}] Error: {}", line, &diag.message);
-            }
-        } else {
-            let mut parser = Parser::new(&tokens);
-
-            match expr::parse(&mut parser) {
-                Ok(ast) => {
-                    let evaluator = Evaluator::new(Box::new(ast));
-                    match evaluator.evaluate() {
-                        Ok(value) => println!("{}", value),
-                        Err(diag) => {
-                            let line = offsets.line(diag.span.end);
-                            eprintln!("[line {}] Error: {}", line, &diag.message);
-                        }
-                    }
-                }
-                Err(_) => {
-                    for diag in parser.diagnostics() {
-                        let line = offsets.line(diag.span.end);
-                        eprintln!("[line {}] Error: {}", line, &diag.message);
-                    }
-                }
-            }
+fn evaluate(sources: &SourceMap, file: FileId, options: &Options) -> ExitCode {
+    let renderer = Renderer::new(sources, options.color).with_format(options.error_format);
+    let Some(tokens) = scan(sources.file(file), &renderer) else {
+        return ExitCode::from(EXIT_COMPILE_ERROR);
+    };
+
+    let mut parser = parser(&tokens, options);
+    let Ok(ast) = expr::parse(&mut parser) else {
+        report(&renderer, parser.diagnostics());
+        return ExitCode::from(EXIT_COMPILE_ERROR);
+    };
+
+    let mut evaluator = evaluator(options);
+    match evaluator.evaluate(&ast) {
+        Ok(value) => {
+            println!("{}", value);
+            ExitCode::SUCCESS
+        }
+        Err(diag) => {
+            eprint!("{}", renderer.render(&diag));
+            ExitCode::from(EXIT_RUNTIME_ERROR)
         }
     }
 }
 
-fn main() {
+fn run(sources: &SourceMap, file: FileId, options: &Options) -> ExitCode {
+    let renderer = Renderer::new(sources, options.color).with_format(options.error_format);
+    let Some(tokens) = scan(sources.file(file), &renderer) else {
+        return ExitCode::from(EXIT_COMPILE_ERROR);
+    };
+
+    let mut parser = parser(&tokens, options);
+    let Ok(program) = stmt::parse(&mut parser) else {
+        report(&renderer, parser.diagnostics());
+        return ExitCode::from(EXIT_COMPILE_ERROR);
+    };
+
+    let mut resolver = Resolver::new();
+    let Ok(locals) = resolver.resolve(&program) else {
+        report(&renderer, resolver.diagnostics());
+        return ExitCode::from(EXIT_COMPILE_ERROR);
+    };
+
+    let mut linter = Linter::new(options.lints.clone());
+    linter.lint(&program);
+    report(&renderer, linter.diagnostics());
+    if options.deny_warnings && !linter.diagnostics().is_empty() {
+        return ExitCode::from(EXIT_COMPILE_ERROR);
+    }
+
+    let mut evaluator = evaluator(options);
+    evaluator.resolve(locals);
+    match evaluator.execute(&program) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(diag) => {
+            eprint!("{}", renderer.render(&diag));
+            ExitCode::from(EXIT_RUNTIME_ERROR)
+        }
+    }
+}
+
+fn main() -> ExitCode {
     let args: Vec<String> = env::args().collect();
-    if args.len() < 3 {
-        eprintln!("Usage: {} tokenize <filename>", args[0]);
-        return;
+
+    let mut options = Options::default();
+    let mut positional = Vec::new();
+    for arg in &args[1..] {
+        if !arg.starts_with("--") {
+            positional.push(arg);
+        } else if let Err(message) = set_option(&mut options, arg) {
+            eprintln!("{}", message);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    }
+
+    if positional.len() < 2 {
+        eprintln!(
+            "Usage: {} [--color] [--error-format=human|short|json] [--max-trace-frames=N] \
+             [--warn=LINT] [--allow=LINT] [--deny-warnings] \
+             [--max-nesting=N] [--max-call-depth=N] [--max-steps=N] <command> <filename>",
+            args[0]
+        );
+        return ExitCode::from(EXIT_USAGE);
     }
 
-    let command = &args[1];
-    let filename = &args[2];
+    let command = positional[0];
+    let filename = positional[1];
 
-    match command.as_str() {
-        "tokenize" => tokenize(filename),
-        "parse" => parse(filename),
-        "evaluate" => evaluate(filename),
+    let command: fn(&SourceMap, FileId, &Options) -> ExitCode = match command.as_str() {
+        "tokenize" => tokenize,
+        "parse" => parse,
+        "evaluate" => evaluate,
+        "run" => run,
         _ => {
             eprintln!("Unknown command: {}", command);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    match fs::read_to_string(filename) {
+        Ok(source) => {
+            let mut sources = SourceMap::new();
+            let file = sources.add(filename.as_str(), source);
+            thread::scope(|scope| {
+                thread::Builder::new()
+                    .stack_size(STACK_SIZE)
+                    .spawn_scoped(scope, || command(&sources, file, &options))
+                    .expect("failed to spawn interpreter thread")
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+        }
+        Err(_) => {
+            eprintln!("Failed to read file {}", filename);
+            ExitCode::from(EXIT_NO_INPUT)
         }
     }
 }
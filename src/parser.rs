@@ -1,60 +1,197 @@
 use crate::{
+    codes::Code,
+    expr::Identifier,
     lexer::Token,
     position::{Diagnostic, Span, WithSpan},
 };
 
+/// Nesting depth allowed unless configured otherwise. Deep enough for any
+/// hand written program while keeping every recursive pass over the AST
+/// well within the native stack.
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+/// Largest nesting depth that can be configured. Together with
+/// [`MAX_CHAIN_LINKS`] it keeps every recursive pass over the AST within the
+/// native stack of the interpreter thread in `main.rs`.
+pub const MAX_NESTING_LIMIT: usize = 16_384;
+
+/// Number of links a chain like `a + b + c` may have, counting every chain
+/// still being parsed. Chains are flat in the source but each link nests the
+/// AST one level deeper, so this matches the depth the evaluator allows.
+pub const MAX_CHAIN_LINKS: usize = 16_384;
+
 pub struct Parser<'a> {
-    tokens: &'a [WithSpan<Token>],
+    tokens: &'a [WithSpan<Token<'a>>],
     current: usize,
     diagnostics: Vec<Diagnostic>,
+    depth: usize,
+    max_depth: usize,
+    links: usize,
+    // set once parsing gave up on the rest of the input
+    abandoned: bool,
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(tokens: &'a [WithSpan<Token>]) -> Self {
+    pub fn new(tokens: &'a [WithSpan<Token<'a>>]) -> Self {
         Self {
             tokens,
             current: 0,
             diagnostics: Vec::new(),
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            links: 0,
+            abandoned: false,
         }
     }
 
+    /// Caps how deeply statements and expressions may be nested, at most
+    /// [`MAX_NESTING_LIMIT`] levels.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    /// Parses `rule` one level of nesting deeper than the caller, giving up
+    /// with a diagnostic instead once the depth limit is reached. Every rule
+    /// that can recurse into itself goes through here, and rules that build
+    /// chains in a loop go through [`Parser::chain`], so the AST can never be
+    /// nested deeper than the two limits together.
+    pub fn nested<T, F>(&mut self, rule: F) -> Result<T, ()>
+    where
+        F: FnOnce(&mut Self) -> Result<T, ()>,
+    {
+        self.deepen()?;
+        let result = rule(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Parses a rule that builds a left-associative chain such as `a + b + c`
+    /// or `f()()` in a loop. Every link wraps the expression built so far, so
+    /// the rule calls [`Parser::link`] once per link. The links are given back
+    /// once the chain is complete.
+    pub fn chain<T, F>(&mut self, rule: F) -> Result<T, ()>
+    where
+        F: FnOnce(&mut Self) -> Result<T, ()>,
+    {
+        let links = self.links;
+        let result = rule(self);
+        self.links = links;
+        result
+    }
+
+    /// Goes one level of nesting deeper, reporting an error once the depth
+    /// limit is reached.
+    pub fn deepen(&mut self) -> Result<(), ()> {
+        if self.depth >= self.max_depth {
+            return self.too_deep(format!(
+                "statements and expressions can be nested at most {} levels deep",
+                self.max_depth
+            ));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Adds a link to the chain being parsed, reporting an error once the
+    /// chains in progress have too many links.
+    pub fn link(&mut self) -> Result<(), ()> {
+        if self.links >= MAX_CHAIN_LINKS {
+            return self.too_deep(format!(
+                "chains like `a + b + c` can have at most {} links",
+                MAX_CHAIN_LINKS
+            ));
+        }
+        self.links += 1;
+        Ok(())
+    }
+
+    /// Reports going over a nesting limit, failing the rule that did.
+    ///
+    /// Going over a limit abandons the rest of the input, as every enclosing
+    /// rule would otherwise report its own unclosed delimiter.
+    fn too_deep(&mut self, help: String) -> Result<(), ()> {
+        self.report(
+            Diagnostic::error("Too much nesting.", self.current_span())
+                .with_code(Code::NestingTooDeep)
+                .with_help(help),
+        );
+        self.abandoned = true;
+        self.current = self.tokens.len().saturating_sub(1);
+        Err(())
+    }
+
     pub fn diagnostics(&self) -> &[Diagnostic] {
         &self.diagnostics
     }
 
-    pub fn error(&mut self, message: &str, span: Span) {
-        self.diagnostics.push(Diagnostic {
-            message: message.to_string(),
-            span,
-        })
+    pub fn error(&mut self, code: Code, message: &str, span: Span) {
+        self.report(Diagnostic::error(message, span).with_code(code))
+    }
+
+    /// Records a fully built diagnostic, e.g. one carrying labels or help.
+    ///
+    /// Token slices don't have to end in `Eof`. Errors found after running
+    /// off the end of one are reported as an unexpected end of input, with
+    /// what was expected moved into the help.
+    pub fn report(&mut self, diagnostic: Diagnostic) {
+        if self.abandoned {
+            return;
+        }
+        if self.is_at_end() {
+            let mut expected = diagnostic.message.trim_end_matches('.').chars();
+            let help = expected
+                .next()
+                .map(|first| first.to_lowercase().chain(expected).collect());
+            self.diagnostics.push(Diagnostic {
+                message: "Unexpected end of input.".to_string(),
+                code: Some(Code::UnexpectedEndOfInput),
+                help,
+                ..diagnostic
+            });
+            return;
+        }
+        self.diagnostics.push(diagnostic)
+    }
+
+    pub fn has_errors(&self) -> bool {
+        !self.diagnostics.is_empty()
     }
 
     pub fn is_at_end(&self) -> bool {
         self.current >= self.tokens.len()
     }
 
-    /// Retrieves interior Token from slice of WithSpan<Token>
-    pub fn peek(&self) -> Option<Token> {
-        // this feels weird, but I like the idea of keeping the tokens
-        // as a slice since we never need to modify, and I'm pretty sure
-        // the clone on the enum variant in the From Impl is very cheap
-        self.tokens.get(self.current).map(Token::from)
+    /// Retrieves interior Token from slice of WithSpan<Token>. Past the end
+    /// of the slice this is `Eof`, whether or not the slice ended with one.
+    pub fn peek(&self) -> Token<'a> {
+        // tokens only borrow their text from the source, so handing back a
+        // copy is as cheap as handing back a reference
+        self.tokens
+            .get(self.current)
+            .map_or(Token::Eof, Token::from)
     }
 
-    pub fn peek_with_span(&self) -> Option<&'a WithSpan<Token>> {
+    pub fn peek_with_span(&self) -> Option<&'a WithSpan<Token<'a>>> {
         self.tokens.get(self.current)
     }
 
-    /// Returns span of current token
+    /// Returns span of current token, or an empty span just past the last
+    /// token once the slice has run out.
     pub fn current_span(&self) -> Span {
-        self.tokens
-            .get(self.current)
-            .map_or(Span::empty(), |t| t.span)
+        match self.tokens.get(self.current).or(self.tokens.last()) {
+            Some(token) if self.is_at_end() => Span {
+                start: token.span.end,
+                end: token.span.end,
+            },
+            Some(token) => token.span,
+            None => Span::empty(),
+        }
     }
 
-    pub fn advance(&mut self) -> WithSpan<Token> {
-        // achtung: could panic! but also I dont think so
-        let token = self.tokens.get(self.current).unwrap().clone();
+    /// Consumes the current token. Past the end of the slice nothing is
+    /// consumed and an `Eof` is handed back instead.
+    pub fn advance(&mut self) -> WithSpan<Token<'a>> {
+        let token = WithSpan::new(self.peek(), self.current_span());
         if !self.is_at_end() {
             self.current += 1;
         }
@@ -62,7 +199,261 @@ impl<'a> Parser<'a> {
     }
 
     pub fn matches(&self, expected: Token) -> bool {
-        expected == self.peek().unwrap()
-        // I am a criminal --------^
+        expected == self.peek()
+    }
+
+    /// Consumes the current token if it matches `expected`, otherwise records
+    /// a diagnostic with the given message at the current token.
+    pub fn expect(&mut self, expected: Token, message: &str) -> Result<WithSpan<Token<'a>>, ()> {
+        match self.peek_with_span() {
+            Some(token) if token.value == expected => Ok(self.advance()),
+            _ => {
+                self.error(Code::ExpectedToken, message, self.current_span());
+                Err(())
+            }
+        }
+    }
+
+    /// Consumes the current token if it is an identifier, otherwise records
+    /// a diagnostic with the given message at the current token.
+    pub fn expect_identifier(&mut self, message: &str) -> Result<WithSpan<Identifier>, ()> {
+        match self.peek() {
+            Token::Identifier(name) => {
+                let token = self.advance();
+                Ok(WithSpan::new(name.to_string(), token.span))
+            }
+            _ => {
+                self.error(Code::ExpectedToken, message, self.current_span());
+                Err(())
+            }
+        }
+    }
+
+    /// Panic-mode recovery. Discards tokens until the start of what is likely
+    /// the next statement, so that parsing can resume and report any further
+    /// independent errors.
+    pub fn synchronize(&mut self) {
+        if self.matches(Token::Eof) {
+            return;
+        }
+        let mut previous = self.advance();
+        while !self.matches(Token::Eof) {
+            if previous.value == Token::Semicolon {
+                return;
+            }
+            if matches!(
+                self.peek(),
+                Token::Class
+                    | Token::Fun
+                    | Token::Var
+                    | Token::For
+                    | Token::If
+                    | Token::While
+                    | Token::Print
+                    | Token::Return
+            ) {
+                return;
+            }
+            previous = self.advance();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Parser;
+    use crate::{
+        codes::Code,
+        expr,
+        lexer::{Scanner, Token},
+        position::{BytePos, Span, WithSpan},
+        stmt,
+    };
+
+    const PROGRAM: &str = "class A < B {\n\
+                           init(x) { this.x = x; }\n\
+                           get() { return super.get() + this.x; }\n\
+                           }\n\
+                           fun f(a, b) { for (var i = 0; i < a; i = i + 1) { if (!b or i == 2) print i; else print -i; } }\n\
+                           var c = A(1).get(\"s\", nil) * 2;\n\
+                           while (c >= 0 and true) c = c / 2;\n";
+
+    /// Xorshift generator, so failing cases can be replayed from the seed.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    fn random_token(rng: &mut Rng) -> Token<'static> {
+        let tokens = [
+            Token::LeftParen,
+            Token::RightParen,
+            Token::LeftBrace,
+            Token::RightBrace,
+            Token::Comma,
+            Token::Dot,
+            Token::Minus,
+            Token::Plus,
+            Token::Semicolon,
+            Token::Slash,
+            Token::Star,
+            Token::Bang,
+            Token::BangEqual,
+            Token::Equal,
+            Token::EqualEqual,
+            Token::Greater,
+            Token::GreaterEqual,
+            Token::Less,
+            Token::LessEqual,
+            Token::Identifier("a"),
+            Token::String("s"),
+            Token::Number("1"),
+            Token::And,
+            Token::Class,
+            Token::Else,
+            Token::False,
+            Token::Fun,
+            Token::For,
+            Token::If,
+            Token::Nil,
+            Token::Or,
+            Token::Print,
+            Token::Return,
+            Token::Super,
+            Token::This,
+            Token::True,
+            Token::Var,
+            Token::While,
+            Token::Eof,
+        ];
+        tokens[rng.below(tokens.len())]
+    }
+
+    /// Gives the tokens consecutive one byte spans.
+    fn with_spans(tokens: Vec<Token>) -> Vec<WithSpan<Token>> {
+        tokens
+            .into_iter()
+            .enumerate()
+            .map(|(i, token)| {
+                let span = Span {
+                    start: BytePos(i as u32),
+                    end: BytePos(i as u32 + 1),
+                };
+                WithSpan::new(token, span)
+            })
+            .collect()
+    }
+
+    /// Parses the tokens as both a program and an expression, checking that
+    /// the parser fails exactly when it reported something, and that it
+    /// only points inside the tokens.
+    fn check(tokens: &[WithSpan<Token>]) {
+        let end = tokens.last().map_or(BytePos(0), |token| token.span.end);
+
+        let mut parser = Parser::new(tokens);
+        parser.set_max_depth(16);
+        let result = stmt::parse(&mut parser).map(drop);
+        check_diagnostics(tokens, &parser, result, end);
+
+        let mut parser = Parser::new(tokens);
+        parser.set_max_depth(16);
+        let result = expr::parse(&mut parser).map(drop);
+        check_diagnostics(tokens, &parser, result, end);
+    }
+
+    fn check_diagnostics(
+        tokens: &[WithSpan<Token>],
+        parser: &Parser,
+        result: Result<(), ()>,
+        end: BytePos,
+    ) {
+        if result.is_err() {
+            assert!(parser.has_errors(), "no diagnostic for {:?}", tokens);
+        }
+        for diag in parser.diagnostics() {
+            assert!(diag.span.end <= end, "{:?} outside of {:?}", diag, tokens);
+        }
+    }
+
+    fn parse_errors(tokens: &[WithSpan<Token>]) -> Vec<(Option<Code>, String)> {
+        let mut parser = Parser::new(tokens);
+        let _ = stmt::parse(&mut parser);
+        parser
+            .diagnostics()
+            .iter()
+            .map(|diag| (diag.code, diag.message.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_running_out_of_tokens_is_unexpected_end_of_input() {
+        let tokens = with_spans(vec![Token::Print, Token::Number("1")]);
+
+        assert_eq!(
+            parse_errors(&tokens),
+            vec![(
+                Some(Code::UnexpectedEndOfInput),
+                "Unexpected end of input.".to_string()
+            )]
+        );
+
+        let mut parser = Parser::new(&tokens);
+        let _ = stmt::parse(&mut parser);
+        let diag = &parser.diagnostics()[0];
+        assert_eq!(diag.help.as_deref(), Some("expected ';' after value"));
+        assert_eq!(diag.span.start, BytePos(2));
+
+        assert!(parse_errors(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_random_tokens_never_panic() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..5_000 {
+            let len = rng.below(48);
+            let tokens = (0..len).map(|_| random_token(&mut rng)).collect();
+            check(&with_spans(tokens));
+        }
+    }
+
+    #[test]
+    fn test_damaged_programs_never_panic() {
+        let program: Vec<Token> = Scanner::new(PROGRAM)
+            .scan()
+            .into_iter()
+            .map(WithSpan::into_inner)
+            .collect();
+
+        // every prefix, none of which but the whole program ends in Eof
+        for len in 0..=program.len() {
+            check(&with_spans(program[..len].to_vec()));
+        }
+
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..2_000 {
+            let mut tokens = program.clone();
+            for _ in 0..=rng.below(4) {
+                let at = rng.below(tokens.len());
+                match rng.below(3) {
+                    0 => {
+                        tokens.remove(at);
+                    }
+                    1 => tokens.insert(at, random_token(&mut rng)),
+                    _ => tokens[at] = random_token(&mut rng),
+                }
+            }
+            tokens.truncate(rng.below(tokens.len() + 1));
+            check(&with_spans(tokens));
+        }
     }
 }
@@ -5,9 +5,13 @@
 //
 // yoink
 
+use std::fmt::Display;
+
+use crate::codes::Code;
+
 /// TypeSafe u32 wrapper with some helpful methods for handling iterating over character's
 /// positions which may or may not be valid ASCII as expected in lox.
-#[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Eq, Ord, Default)]
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Eq, Ord, Hash, Default)]
 pub struct BytePos(pub u32);
 
 impl BytePos {
@@ -16,31 +20,118 @@ impl BytePos {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Eq, Hash)]
 pub struct Span {
     pub start: BytePos,
     pub end: BytePos,
 }
 
-/// Simple Error Diagnostics Wrapper.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "Error"),
+            Severity::Warning => write!(f, "Warning"),
+        }
+    }
+}
+
+/// Secondary span attached to a diagnostic, pointing at related source such
+/// as where an unclosed delimiter was opened.
+#[derive(Debug)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+/// Entry of the stack trace attached to a runtime error.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceFrame {
+    /// `function` was executing `span` when the error unwound through it.
+    /// Code outside of any function is reported as `script`.
+    Call { function: String, span: Span },
+    /// Number of frames left out of a trace that went over the frame cap.
+    Omitted(usize),
+}
+
+/// Error Diagnostics Wrapper.
 ///
 /// Contains a message and a Span (starting and ending BytePos) of the
-/// offending token.
+/// offending token, along with a severity, an optional stable error code,
+/// secondary labelled spans and help text. Runtime errors raised inside a
+/// function call also carry a stack trace, innermost frame first.
+#[derive(Debug)]
 pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Option<Code>,
     pub span: Span,
     pub message: String,
+    pub labels: Vec<Label>,
+    pub help: Option<String>,
+    pub trace: Vec<TraceFrame>,
 }
 
 impl Diagnostic {
     pub fn new<T>(message: T, start: BytePos, end: BytePos) -> Self
+    where
+        T: Into<String>,
+    {
+        Self::error(message, Span { start, end })
+    }
+
+    pub fn error<T>(message: T, span: Span) -> Self
     where
         T: Into<String>,
     {
         Self {
+            severity: Severity::Error,
+            code: None,
+            span,
             message: message.into(),
-            span: Span { start, end },
+            labels: Vec::new(),
+            help: None,
+            trace: Vec::new(),
         }
     }
+
+    pub fn warning<T>(message: T, span: Span) -> Self
+    where
+        T: Into<String>,
+    {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(message, span)
+        }
+    }
+
+    pub fn with_code(mut self, code: Code) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn with_label<T>(mut self, span: Span, message: T) -> Self
+    where
+        T: Into<String>,
+    {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_help<T>(mut self, help: T) -> Self
+    where
+        T: Into<String>,
+    {
+        self.help = Some(help.into());
+        self
+    }
 }
 
 /// Wrapper for various types within the interpreter. Allows for keeping the starting
@@ -56,6 +147,7 @@ impl<T> WithSpan<T> {
         Self { value, span }
     }
 
+    #[allow(dead_code)]
     pub const fn empty(value: T) -> Self {
         Self {
             value,
@@ -132,7 +224,7 @@ impl LineOffsets {
         Self { offsets, len }
     }
 
-    /// Finds the line number of a BytePos in the
+    /// Finds the (1-based) line number of a BytePos in the
     /// source data.
     ///
     /// Panics if the given byte position exceeds the length
@@ -146,11 +238,17 @@ impl LineOffsets {
         // array. Since we only store the offsets of the \n chars, this in effect
         // gives us an n log n method to find the closest preceding newline for
         // any given bytepos.
+        // an exact hit means the position is the first byte of a line
         match self.offsets.binary_search(&offset) {
-            Ok(line) => line,
+            Ok(line) => line + 1,
             Err(line) => line,
         }
     }
+
+    /// Byte position at which the given (1-based) line starts.
+    pub fn line_start(&self, line: usize) -> BytePos {
+        BytePos(self.offsets[line - 1])
+    }
 }
 
 #[cfg(test)]
@@ -164,4 +262,14 @@ mod tests {
 
         assert_eq!(res, 2);
     }
+
+    #[test]
+    fn test_offset_at_start_of_line_gives_that_line() {
+        let of = LineOffsets::new("line1\nline2\n");
+
+        assert_eq!(of.line(BytePos(0)), 1);
+        assert_eq!(of.line(BytePos(6)), 2);
+        assert_eq!(of.line(BytePos(12)), 3);
+        assert_eq!(of.line_start(2), BytePos(6));
+    }
 }
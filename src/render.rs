@@ -0,0 +1,545 @@
+use crate::{
+    position::{Diagnostic, Severity, Span, TraceFrame},
+    source::{SourceFile, SourceMap},
+};
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Renders diagnostics against the sources they were produced from, quoting
+/// the offending line and underlining the span with carets. Labels are
+/// underlined with dashes and help text is added at the end:
+///
+/// ```text
+/// [line 1] Error[E0101]: Unmatched parentheses.
+///  --> main.lox:1:13
+///   |
+/// 1 | print (1 + 2;
+///   |             ^
+///   |       - unmatched `(` opened here
+/// ```
+///
+/// Labels pointing into a different source than the error itself are
+/// introduced with a `:::` line naming that source.
+///
+/// Runtime errors raised inside a function are followed by their stack
+/// trace, one `[line 12] in fib` line per frame.
+///
+/// Color is opt-in, the plain output is meant to be safe to write to logs.
+/// Tools can ask for one line per diagnostic instead, see [`ErrorFormat`].
+pub struct Renderer<'a> {
+    sources: &'a SourceMap,
+    color: bool,
+    format: ErrorFormat,
+}
+
+/// Layout diagnostics are rendered in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorFormat {
+    /// Source snippets with underlined spans, labels, help and stack traces.
+    #[default]
+    Human,
+    /// `path:line:col: Error[E0100]: message`, one line per diagnostic.
+    Short,
+    /// One JSON object per line, for editors and CI annotators.
+    Json,
+}
+
+impl ErrorFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "human" => Some(ErrorFormat::Human),
+            "short" => Some(ErrorFormat::Short),
+            "json" => Some(ErrorFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new(sources: &'a SourceMap, color: bool) -> Self {
+        Self {
+            sources,
+            color,
+            format: ErrorFormat::default(),
+        }
+    }
+
+    pub fn with_format(mut self, format: ErrorFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Renders a diagnostic in the configured format. The result always ends
+    /// with a newline.
+    pub fn render(&self, diag: &Diagnostic) -> String {
+        match self.format {
+            ErrorFormat::Human => self.human(diag),
+            ErrorFormat::Short => self.short(diag),
+            ErrorFormat::Json => self.json(diag),
+        }
+    }
+
+    fn human(&self, diag: &Diagnostic) -> String {
+        let file = self.sources.lookup(diag.span.start);
+        let header_line = file.line(diag.span.start);
+        let style = match diag.severity {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        };
+
+        // the primary span is underlined with carets, labels with dashes.
+        // sorting is stable so the primary span stays first on its line, and
+        // labels in the primary source come before those in other sources
+        let mut annotations = vec![(file, diag.span, '^', style, "")];
+        for label in &diag.labels {
+            let label_file = self.sources.lookup(label.span.start);
+            annotations.push((label_file, label.span, '-', BLUE, label.message.as_str()));
+        }
+        annotations.sort_by_key(|(annotated, span, ..)| {
+            (
+                annotated.id() != file.id(),
+                annotated.id().0,
+                annotated.line(span.start),
+            )
+        });
+
+        let width = annotations
+            .iter()
+            .map(|(annotated, span, ..)| annotated.line(span.start).to_string().len())
+            .max()
+            .unwrap_or(1);
+        let gutter = " ".repeat(width);
+        let bar = self.paint(BLUE, "|");
+
+        let mut out = format!(
+            "[line {}] {}{}\n",
+            header_line,
+            self.paint(style, &self.title(diag)),
+            self.paint(BOLD, &format!(": {}", diag.message)),
+        );
+        out.push_str(&format!(
+            "{}{} {}\n",
+            gutter,
+            self.paint(BLUE, "-->"),
+            self.path(file, diag.span)
+        ));
+        out.push_str(&format!("{} {}\n", gutter, bar));
+
+        let mut printed_line = None;
+        for (annotated, span, marker, marker_style, message) in annotations {
+            if annotated.id() != file.id()
+                && !matches!(printed_line, Some((id, _)) if id == annotated.id())
+            {
+                out.push_str(&format!(
+                    "{}{} {}\n",
+                    gutter,
+                    self.paint(BLUE, ":::"),
+                    self.path(annotated, span)
+                ));
+            }
+            let (text, padding, markers) = self.underline(annotated, span, marker);
+            let line = annotated.line(span.start);
+            if printed_line != Some((annotated.id(), line)) {
+                out.push_str(&format!(
+                    "{} {} {}\n",
+                    self.paint(BLUE, &format!("{:>width$}", line)),
+                    bar,
+                    text
+                ));
+                printed_line = Some((annotated.id(), line));
+            }
+            let annotation = if message.is_empty() {
+                markers
+            } else {
+                format!("{} {}", markers, message)
+            };
+            out.push_str(&format!(
+                "{} {} {}{}\n",
+                gutter,
+                bar,
+                padding,
+                self.paint(marker_style, &annotation)
+            ));
+        }
+
+        if let Some(help) = &diag.help {
+            out.push_str(&format!(
+                "{} {} {}\n",
+                gutter,
+                self.paint(BLUE, "="),
+                self.paint(BOLD, &format!("help: {}", help))
+            ));
+        }
+
+        for frame in &diag.trace {
+            match frame {
+                TraceFrame::Call { function, span } => out.push_str(&format!(
+                    "[line {}] in {}\n",
+                    self.sources.lookup(span.start).line(span.start),
+                    function
+                )),
+                TraceFrame::Omitted(count) => {
+                    out.push_str(&format!("... {} frames omitted ...\n", count))
+                }
+            }
+        }
+        out
+    }
+
+    fn short(&self, diag: &Diagnostic) -> String {
+        let file = self.sources.lookup(diag.span.start);
+        format!(
+            "{}: {}: {}\n",
+            self.path(file, diag.span),
+            self.title(diag),
+            diag.message
+        )
+    }
+
+    /// Renders a diagnostic as a single line JSON object:
+    ///
+    /// ```text
+    /// {"file":"main.lox","severity":"error","code":"E0100",
+    ///  "message":"Expected expression.","span":{"start":11,"end":12},
+    ///  "range":{"start":{"line":1,"column":12},"end":{"line":1,"column":13}},
+    ///  "labels":[],"help":null,"trace":[]}
+    /// ```
+    ///
+    /// Byte offsets are relative to the start of the file, the end of spans
+    /// and ranges is exclusive. Labels carry their own file, span, range and
+    /// message, trace frames their function, file and line.
+    fn json(&self, diag: &Diagnostic) -> String {
+        let labels: Vec<String> = diag
+            .labels
+            .iter()
+            .map(|label| format!("{{{}}}", self.json_location(label.span, &label.message)))
+            .collect();
+        let trace: Vec<String> = diag
+            .trace
+            .iter()
+            .map(|frame| match frame {
+                TraceFrame::Call { function, span } => {
+                    let file = self.sources.lookup(span.start);
+                    format!(
+                        "{{\"function\":{},\"file\":{},\"line\":{}}}",
+                        json_string(function),
+                        json_string(file.name()),
+                        file.line(span.start)
+                    )
+                }
+                TraceFrame::Omitted(count) => format!("{{\"omitted\":{}}}", count),
+            })
+            .collect();
+
+        format!(
+            "{{{},\"severity\":{},\"code\":{},\"labels\":[{}],\"help\":{},\"trace\":[{}]}}\n",
+            self.json_location(diag.span, &diag.message),
+            json_string(&diag.severity.to_string().to_lowercase()),
+            diag.code
+                .map_or("null".to_string(), |code| json_string(code.as_str())),
+            labels.join(","),
+            diag.help.as_deref().map_or("null".to_string(), json_string),
+            trace.join(","),
+        )
+    }
+
+    /// JSON members locating a message in its file, without the braces.
+    fn json_location(&self, span: Span, message: &str) -> String {
+        let file = self.sources.lookup(span.start);
+        let (start_line, start_column) = file.location(span.start);
+        let (end_line, end_column) = file.location(span.end);
+        format!(
+            "\"file\":{},\"message\":{},\"span\":{{\"start\":{},\"end\":{}}},\
+             \"range\":{{\"start\":{{\"line\":{},\"column\":{}}},\
+             \"end\":{{\"line\":{},\"column\":{}}}}}",
+            json_string(file.name()),
+            json_string(message),
+            file.local(span.start),
+            file.local(span.end),
+            start_line,
+            start_column,
+            end_line,
+            end_column
+        )
+    }
+
+    /// Severity and code, as in `Error[E0100]`.
+    fn title(&self, diag: &Diagnostic) -> String {
+        match diag.code {
+            Some(code) => format!("{}[{}]", diag.severity, code),
+            None => diag.severity.to_string(),
+        }
+    }
+
+    /// `path:line:col` of the start of a span.
+    fn path(&self, file: &SourceFile, span: Span) -> String {
+        let (line, column) = file.location(span.start);
+        format!("{}:{}:{}", file.name(), line, column)
+    }
+
+    /// Returns the text of the line a span starts on, the whitespace needed
+    /// to line up with the span and the markers underlining it. Spans running
+    /// over multiple lines are only underlined on their first line.
+    fn underline(
+        &self,
+        file: &'a SourceFile,
+        span: Span,
+        marker: char,
+    ) -> (&'a str, String, String) {
+        let source = file.source();
+        let line_start = file.line_start(file.line(span.start));
+        let text = self.line_text(source, line_start);
+        let line_end = line_start + text.len();
+
+        let start = file.local(span.start).clamp(line_start, line_end);
+        let end = file.local(span.end).clamp(start, line_end);
+        let padding = source[line_start..start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let count = source[start..end].chars().count().max(1);
+        (text, padding, marker.to_string().repeat(count))
+    }
+
+    /// Text of the line starting at the given byte offset, without its line
+    /// terminator.
+    fn line_text(&self, source: &'a str, line_start: usize) -> &'a str {
+        let rest = &source[line_start..];
+        let text = rest.split('\n').next().unwrap_or_default();
+        text.strip_suffix('\r').unwrap_or(text)
+    }
+
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+/// Quotes a string for JSON output, escaping quotes, backslashes and
+/// control characters.
+fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ErrorFormat, Renderer};
+    use crate::{
+        codes::Code,
+        position::{BytePos, Diagnostic, Span, TraceFrame},
+        source::SourceMap,
+    };
+
+    fn single(source: &str) -> SourceMap {
+        let mut sources = SourceMap::new();
+        sources.add("test.lox", source);
+        sources
+    }
+
+    fn span(start: u32, end: u32) -> Span {
+        Span {
+            start: BytePos(start),
+            end: BytePos(end),
+        }
+    }
+
+    #[test]
+    fn test_renders_snippet_with_underline() {
+        let source = "var a = 1;\nprint (1 + ;\n";
+        let sources = single(source);
+        let renderer = Renderer::new(&sources, false);
+        let diag = Diagnostic::new("Expected expression.", BytePos(22), BytePos(23))
+            .with_code(Code::ExpectedExpression);
+
+        assert_eq!(
+            renderer.render(&diag),
+            "[line 2] Error[E0100]: Expected expression.\n\
+            \x20--> test.lox:2:12\n\
+            \x20 |\n\
+            2 | print (1 + ;\n\
+            \x20 |            ^\n"
+        );
+    }
+
+    #[test]
+    fn test_multi_line_spans_are_reported_at_their_start() {
+        let source = "print 1 +\n\n  nil;\n";
+        let sources = single(source);
+        let renderer = Renderer::new(&sources, false);
+        let diag = Diagnostic::new("Operands must be numbers.", BytePos(6), BytePos(16))
+            .with_code(Code::InvalidOperand);
+
+        assert_eq!(
+            renderer.render(&diag),
+            "[line 1] Error[E0300]: Operands must be numbers.\n\
+            \x20--> test.lox:1:7\n\
+            \x20 |\n\
+            1 | print 1 +\n\
+            \x20 |       ^^^\n"
+        );
+    }
+
+    #[test]
+    fn test_columns_count_characters_not_bytes() {
+        let source = "print \"héllo\" + ;";
+        let sources = single(source);
+        let renderer = Renderer::new(&sources, false);
+        let plus = source.find('+').unwrap() as u32;
+
+        assert_eq!(
+            sources.lookup(BytePos(plus)).location(BytePos(plus)),
+            (1, 15)
+        );
+        assert!(renderer
+            .render(&Diagnostic::new("bad", BytePos(6), BytePos(14)))
+            .ends_with("|       ^^^^^^^\n"));
+    }
+
+    #[test]
+    fn test_renders_labels_and_help() {
+        let source = "{\n  print (1 + 2;\n";
+        let sources = single(source);
+        let renderer = Renderer::new(&sources, false);
+        let diag = Diagnostic::error("Unmatched parentheses.", span(16, 17))
+            .with_code(Code::UnclosedDelimiter)
+            .with_label(span(10, 11), "unmatched `(` opened here")
+            .with_label(span(0, 1), "block opened here")
+            .with_help("add a `)`");
+
+        assert_eq!(
+            renderer.render(&diag),
+            "[line 2] Error[E0101]: Unmatched parentheses.\n\
+            \x20--> test.lox:2:15\n\
+            \x20 |\n\
+            1 | {\n\
+            \x20 | - block opened here\n\
+            2 |   print (1 + 2;\n\
+            \x20 |               ^\n\
+            \x20 |         - unmatched `(` opened here\n\
+            \x20 = help: add a `)`\n"
+        );
+    }
+
+    #[test]
+    fn test_renders_paths_and_labels_in_other_sources() {
+        let mut sources = SourceMap::new();
+        sources.add("lib.lox", "fun f() {}\n");
+        let main = sources.add("main.lox", "var f;\nf();\n");
+        let base = sources.file(main).base().0;
+        let diag = Diagnostic::error("Already declared.", span(base, base + 1))
+            .with_label(span(4, 5), "previously declared here");
+
+        assert_eq!(
+            Renderer::new(&sources, false).render(&diag),
+            "[line 1] Error: Already declared.\n\
+            \x20--> main.lox:1:1\n\
+            \x20 |\n\
+            1 | var f;\n\
+            \x20 | ^\n\
+            \x20::: lib.lox:1:5\n\
+            1 | fun f() {}\n\
+            \x20 |     - previously declared here\n"
+        );
+    }
+
+    #[test]
+    fn test_renders_stack_trace() {
+        let source = "fun f() {\n  -nil;\n}\nf();\n";
+        let sources = single(source);
+        let renderer = Renderer::new(&sources, false);
+        let mut diag = Diagnostic::error("Operand must be a number.", span(12, 13));
+        diag.trace = vec![
+            TraceFrame::Call {
+                function: "f".to_string(),
+                span: span(12, 13),
+            },
+            TraceFrame::Omitted(3),
+            TraceFrame::Call {
+                function: "script".to_string(),
+                span: span(20, 23),
+            },
+        ];
+
+        assert!(renderer.render(&diag).ends_with(
+            "[line 2] in f\n\
+            ... 3 frames omitted ...\n\
+            [line 4] in script\n"
+        ));
+    }
+
+    #[test]
+    fn test_renders_short_format() {
+        let sources = single("var a = 1;\nprint (1 + ;\n");
+        let diag = Diagnostic::error("Expected expression.", span(22, 23))
+            .with_code(Code::ExpectedExpression)
+            .with_help("ignored");
+
+        assert_eq!(
+            Renderer::new(&sources, true)
+                .with_format(ErrorFormat::Short)
+                .render(&diag),
+            "test.lox:2:12: Error[E0100]: Expected expression.\n"
+        );
+    }
+
+    #[test]
+    fn test_renders_json_format() {
+        let mut sources = SourceMap::new();
+        sources.add("other.lox", "");
+        let file = sources.add("say \"hi\".lox", "{\n  print (1 + 2;\n");
+        let base = sources.file(file).base().0;
+        let mut diag = Diagnostic::error("Unmatched \"(\".", span(base + 16, base + 17))
+            .with_code(Code::UnclosedDelimiter)
+            .with_label(span(base + 10, base + 11), "opened here");
+        diag.trace = vec![TraceFrame::Omitted(2)];
+
+        assert_eq!(
+            Renderer::new(&sources, false)
+                .with_format(ErrorFormat::Json)
+                .render(&diag),
+            "{\"file\":\"say \\\"hi\\\".lox\",\"message\":\"Unmatched \\\"(\\\".\",\
+             \"span\":{\"start\":16,\"end\":17},\
+             \"range\":{\"start\":{\"line\":2,\"column\":15},\"end\":{\"line\":2,\"column\":16}},\
+             \"severity\":\"error\",\"code\":\"E0101\",\
+             \"labels\":[{\"file\":\"say \\\"hi\\\".lox\",\"message\":\"opened here\",\
+             \"span\":{\"start\":10,\"end\":11},\
+             \"range\":{\"start\":{\"line\":2,\"column\":9},\"end\":{\"line\":2,\"column\":10}}}],\
+             \"help\":null,\"trace\":[{\"omitted\":2}]}\n"
+        );
+    }
+
+    #[test]
+    fn test_color_is_opt_in() {
+        let diag = Diagnostic::new("oops", BytePos(0), BytePos(1));
+
+        let sources = single("$");
+
+        assert!(!Renderer::new(&sources, false)
+            .render(&diag)
+            .contains('\x1b'));
+        assert!(Renderer::new(&sources, true)
+            .render(&diag)
+            .contains("\x1b[1;31m^"));
+    }
+}
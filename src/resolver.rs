@@ -0,0 +1,399 @@
+use std::collections::HashMap;
+
+use crate::{
+    codes::Code,
+    expr::{Expr, Identifier},
+    position::{Diagnostic, Span, WithSpan},
+    stmt::{ClassDecl, FunctionDecl, Stmt},
+};
+
+/// Scope distance of every resolved local variable use, keyed by the span of
+/// the expression referencing it. Uses missing from the map are globals.
+pub type Locals = HashMap<Span, usize>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FunctionKind {
+    None,
+    Function,
+    Initializer,
+    Method,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ClassKind {
+    None,
+    Class,
+    Subclass,
+}
+
+/// A name declared in a local scope.
+struct Binding {
+    span: Span,
+    // whether the initializer of the declaration has finished resolving
+    defined: bool,
+}
+
+/// Static pass run between parsing and evaluation. Binds each variable use to
+/// the number of scopes between it and its declaration, and reports errors
+/// that can be caught without running the program.
+pub struct Resolver {
+    scopes: Vec<HashMap<Identifier, Binding>>,
+    locals: Locals,
+    function: FunctionKind,
+    class: ClassKind,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self {
+            scopes: Vec::new(),
+            locals: HashMap::new(),
+            function: FunctionKind::None,
+            class: ClassKind::None,
+            diagnostics: Vec::new(),
+        }
+    }
+
+    pub fn resolve(&mut self, statements: &[WithSpan<Stmt>]) -> Result<Locals, ()> {
+        self.resolve_statements(statements);
+        if self.diagnostics.is_empty() {
+            Ok(std::mem::take(&mut self.locals))
+        } else {
+            Err(())
+        }
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    fn error(&mut self, code: Code, message: &str, span: Span) {
+        self.diagnostics
+            .push(Diagnostic::error(message, span).with_code(code))
+    }
+
+    fn resolve_statements(&mut self, statements: &[WithSpan<Stmt>]) {
+        for statement in statements {
+            self.resolve_statement(statement);
+        }
+    }
+
+    fn resolve_statement(&mut self, s: &WithSpan<Stmt>) {
+        match &s.value {
+            Stmt::Block(statements) => {
+                self.begin_scope();
+                self.resolve_statements(statements);
+                self.end_scope();
+            }
+            Stmt::Class(declaration) => self.resolve_class(declaration),
+            Stmt::Expression(expr) | Stmt::Print(expr) => self.resolve_expression(expr),
+            Stmt::Function(declaration) => {
+                self.declare(&declaration.name);
+                self.define(&declaration.name);
+                self.resolve_function(declaration, FunctionKind::Function);
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.resolve_expression(condition);
+                self.resolve_statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.resolve_statement(else_branch);
+                }
+            }
+            Stmt::Return(value) => {
+                if self.function == FunctionKind::None {
+                    self.error(
+                        Code::TopLevelReturn,
+                        "Can't return from top-level code.",
+                        s.span,
+                    );
+                }
+                if let Some(value) = value {
+                    if self.function == FunctionKind::Initializer {
+                        self.diagnostics.push(
+                            Diagnostic::error("Can't return a value from an initializer.", s.span)
+                                .with_code(Code::ReturnFromInitializer)
+                                .with_help(
+                                    "initializers always return `this`, use a bare `return;`",
+                                ),
+                        );
+                    }
+                    self.resolve_expression(value);
+                }
+            }
+            Stmt::Var { name, initializer } => {
+                self.declare(name);
+                if let Some(initializer) = initializer {
+                    self.resolve_expression(initializer);
+                }
+                self.define(name);
+            }
+            Stmt::While { condition, body } => {
+                self.resolve_expression(condition);
+                self.resolve_statement(body);
+            }
+        }
+    }
+
+    fn resolve_class(&mut self, declaration: &ClassDecl) {
+        let enclosing = std::mem::replace(&mut self.class, ClassKind::Class);
+        self.declare(&declaration.name);
+        self.define(&declaration.name);
+
+        if let Some(superclass) = &declaration.superclass {
+            if let Expr::Variable(name) = &superclass.value {
+                if name.value == declaration.name.value {
+                    self.error(
+                        Code::InheritsFromSelf,
+                        "A class can't inherit from itself.",
+                        superclass.span,
+                    );
+                }
+            }
+            self.class = ClassKind::Subclass;
+            self.resolve_expression(superclass);
+
+            // methods close over an extra scope holding `super`
+            self.begin_scope();
+            self.define(&WithSpan::new("super".to_string(), superclass.span));
+        }
+
+        self.begin_scope();
+        self.define(&WithSpan::new("this".to_string(), declaration.name.span));
+        for method in &declaration.methods {
+            let kind = if method.name.value == "init" {
+                FunctionKind::Initializer
+            } else {
+                FunctionKind::Method
+            };
+            self.resolve_function(method, kind);
+        }
+        self.end_scope();
+
+        if declaration.superclass.is_some() {
+            self.end_scope();
+        }
+        self.class = enclosing;
+    }
+
+    fn resolve_function(&mut self, declaration: &FunctionDecl, kind: FunctionKind) {
+        let enclosing = std::mem::replace(&mut self.function, kind);
+        self.begin_scope();
+        for param in &declaration.params {
+            self.declare(param);
+            self.define(param);
+        }
+        self.resolve_statements(&declaration.body);
+        self.end_scope();
+        self.function = enclosing;
+    }
+
+    fn resolve_expression(&mut self, e: &WithSpan<Expr>) {
+        match &e.value {
+            Expr::Literal(_) => {}
+            Expr::Unary { right, .. } => self.resolve_expression(right),
+            Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+                self.resolve_expression(left);
+                self.resolve_expression(right);
+            }
+            Expr::Grouping(expr) => self.resolve_expression(expr),
+            Expr::Call { callee, arguments } => {
+                self.resolve_expression(callee);
+                for argument in arguments {
+                    self.resolve_expression(argument);
+                }
+            }
+            Expr::Get { object, .. } => self.resolve_expression(object),
+            Expr::Set { object, value, .. } => {
+                self.resolve_expression(value);
+                self.resolve_expression(object);
+            }
+            Expr::Super { .. } => {
+                match self.class {
+                    ClassKind::None => {
+                        self.error(
+                            Code::InvalidSuper,
+                            "Can't use 'super' outside of a class.",
+                            e.span,
+                        );
+                        return;
+                    }
+                    ClassKind::Class => {
+                        self.error(
+                            Code::InvalidSuper,
+                            "Can't use 'super' in a class with no superclass.",
+                            e.span,
+                        );
+                        return;
+                    }
+                    ClassKind::Subclass => {}
+                }
+                self.resolve_local(e.span, "super");
+            }
+            Expr::This => {
+                if self.class == ClassKind::None {
+                    self.error(
+                        Code::ThisOutsideClass,
+                        "Can't use 'this' outside of a class.",
+                        e.span,
+                    );
+                    return;
+                }
+                self.resolve_local(e.span, "this");
+            }
+            Expr::Variable(name) => {
+                let in_initializer = self
+                    .scopes
+                    .last()
+                    .and_then(|scope| scope.get(&name.value))
+                    .is_some_and(|binding| !binding.defined);
+                if in_initializer {
+                    self.error(
+                        Code::ReadInOwnInitializer,
+                        "Can't read local variable in its own initializer.",
+                        name.span,
+                    );
+                }
+                self.resolve_local(e.span, &name.value);
+            }
+            Expr::Assign { name, value } => {
+                self.resolve_expression(value);
+                self.resolve_local(e.span, &name.value);
+            }
+        }
+    }
+
+    /// Records how many scopes out from the innermost one `name` was found
+    /// in. Names not found in any scope are left for the globals.
+    fn resolve_local(&mut self, span: Span, name: &str) {
+        let found = self
+            .scopes
+            .iter()
+            .rev()
+            .position(|scope| scope.contains_key(name));
+        if let Some(distance) = found {
+            self.locals.insert(span, distance);
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &WithSpan<Identifier>) {
+        let Some(scope) = self.scopes.last_mut() else {
+            return; // globals may be redeclared freely
+        };
+        if let Some(previous) = scope.get(&name.value) {
+            let diagnostic = Diagnostic::error(
+                "Already a variable with this name in this scope.",
+                name.span,
+            )
+            .with_code(Code::AlreadyDeclared)
+            .with_label(previous.span, "previously declared here");
+            self.diagnostics.push(diagnostic);
+            return;
+        }
+        scope.insert(
+            name.value.clone(),
+            Binding {
+                span: name.span,
+                defined: false,
+            },
+        );
+    }
+
+    fn define(&mut self, name: &WithSpan<Identifier>) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(
+                name.value.clone(),
+                Binding {
+                    span: name.span,
+                    defined: true,
+                },
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Resolver;
+    use crate::{lexer::Scanner, parser::Parser, stmt};
+
+    fn resolve_errors(source: &str) -> Vec<String> {
+        let tokens = Scanner::new(source).scan();
+        let mut parser = Parser::new(&tokens);
+        let program = stmt::parse(&mut parser).expect("program should parse");
+        let mut resolver = Resolver::new();
+        let _ = resolver.resolve(&program);
+        resolver
+            .diagnostics()
+            .iter()
+            .map(|diag| diag.message.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_reports_static_errors() {
+        assert_eq!(
+            resolve_errors("var a = 1; { var a = a; }"),
+            vec!["Can't read local variable in its own initializer."]
+        );
+        assert_eq!(
+            resolve_errors("return 1;"),
+            vec!["Can't return from top-level code."]
+        );
+        assert_eq!(
+            resolve_errors("fun f(a) { var a; } { var b; var b; }"),
+            vec![
+                "Already a variable with this name in this scope.",
+                "Already a variable with this name in this scope."
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reports_class_errors() {
+        assert_eq!(
+            resolve_errors("print this; fun f() { return this; }"),
+            vec![
+                "Can't use 'this' outside of a class.",
+                "Can't use 'this' outside of a class."
+            ]
+        );
+        assert_eq!(
+            resolve_errors("class A { init() { return 1; } other() { return 2; } }"),
+            vec!["Can't return a value from an initializer."]
+        );
+        assert!(resolve_errors("class A { init() { return; } }").is_empty());
+    }
+
+    #[test]
+    fn test_reports_inheritance_errors() {
+        assert_eq!(
+            resolve_errors("class A < A {}"),
+            vec!["A class can't inherit from itself."]
+        );
+        assert_eq!(
+            resolve_errors("super.method(); class A { f() { super.f(); } }"),
+            vec![
+                "Can't use 'super' outside of a class.",
+                "Can't use 'super' in a class with no superclass."
+            ]
+        );
+    }
+
+    #[test]
+    fn test_globals_are_not_resolved() {
+        assert!(resolve_errors("var a = 1; var a = a; print a;").is_empty());
+    }
+}
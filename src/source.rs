@@ -0,0 +1,149 @@
+use crate::position::{BytePos, LineOffsets, Span};
+
+/// Index of a source registered with a [`SourceMap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileId(pub u32);
+
+/// A file, REPL line or string of lox source. Its positions start at `base`
+/// rather than zero so that spans stay unique across every registered source.
+pub struct SourceFile {
+    id: FileId,
+    name: String,
+    source: String,
+    base: BytePos,
+    offsets: LineOffsets,
+}
+
+impl SourceFile {
+    pub fn id(&self) -> FileId {
+        self.id
+    }
+
+    /// Path of the file, or a placeholder such as `<repl>` for sources that
+    /// did not come from disk.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Global position of the first byte of the source.
+    pub fn base(&self) -> BytePos {
+        self.base
+    }
+
+    /// Span covering the whole source, including the position just past its
+    /// last byte where `Eof` is reported.
+    pub fn span(&self) -> Span {
+        Span {
+            start: self.base,
+            end: BytePos(self.base.0 + self.source.len() as u32),
+        }
+    }
+
+    pub fn contains(&self, pos: BytePos) -> bool {
+        let span = self.span();
+        span.start <= pos && pos <= span.end
+    }
+
+    /// Offset of a global position from the start of this source.
+    pub fn local(&self, pos: BytePos) -> usize {
+        (pos.0 - self.base.0) as usize
+    }
+
+    /// Finds the 1-based line of a global position.
+    pub fn line(&self, pos: BytePos) -> usize {
+        self.offsets.line(BytePos(self.local(pos) as u32))
+    }
+
+    /// Offset from the start of this source at which the given (1-based)
+    /// line starts.
+    pub fn line_start(&self, line: usize) -> usize {
+        self.offsets.line_start(line).0 as usize
+    }
+
+    /// Finds the 1-based line and column of a global position. Columns count
+    /// chars rather than bytes, so multi-byte characters take up a single
+    /// column.
+    pub fn location(&self, pos: BytePos) -> (usize, usize) {
+        let line = self.line(pos);
+        let column = self
+            .source
+            .get(self.line_start(line)..self.local(pos))
+            .map_or(0, |prefix| prefix.chars().count());
+        (line, column + 1)
+    }
+}
+
+/// Every source the interpreter has been handed. Sources are laid out one
+/// after the other in a single position space, each starting past the end
+/// of the one before it, so a span alone identifies the source it points
+/// into.
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add<N, S>(&mut self, name: N, source: S) -> FileId
+    where
+        N: Into<String>,
+        S: Into<String>,
+    {
+        // leave a gap of one position so the end of one source is never the
+        // start of the next
+        let base = self
+            .files
+            .last()
+            .map_or(BytePos(0), |file| BytePos(file.span().end.0 + 1));
+        let id = FileId(self.files.len() as u32);
+        let source = source.into();
+        self.files.push(SourceFile {
+            id,
+            name: name.into(),
+            offsets: LineOffsets::new(&source),
+            source,
+            base,
+        });
+        id
+    }
+
+    pub fn file(&self, id: FileId) -> &SourceFile {
+        &self.files[id.0 as usize]
+    }
+
+    /// Finds the source a global position belongs to.
+    ///
+    /// Panics if the position is not inside any registered source.
+    pub fn lookup(&self, pos: BytePos) -> &SourceFile {
+        let index = self.files.partition_point(|file| file.base <= pos);
+        let file = &self.files[index.saturating_sub(1)];
+        assert!(file.contains(pos), "position outside of every source");
+        file
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileId, SourceMap};
+    use crate::position::BytePos;
+
+    #[test]
+    fn test_sources_get_distinct_position_ranges() {
+        let mut sources = SourceMap::new();
+        let a = sources.add("a.lox", "print 1;\n");
+        let b = sources.add("b.lox", "var x;\nprint x;");
+
+        assert_eq!((a, b), (FileId(0), FileId(1)));
+        assert_eq!(sources.file(b).base(), BytePos(10));
+        assert_eq!(sources.lookup(BytePos(9)).name(), "a.lox");
+        assert_eq!(sources.lookup(BytePos(10)).name(), "b.lox");
+        assert_eq!(sources.lookup(BytePos(23)).location(BytePos(23)), (2, 7));
+    }
+}
@@ -0,0 +1,466 @@
+use std::rc::Rc;
+
+use crate::{
+    codes::Code,
+    expr::{self, Expr, Identifier},
+    lexer::Token,
+    parser::Parser,
+    position::{Diagnostic, Span, WithSpan},
+};
+
+#[derive(Debug)]
+pub enum Stmt<'src> {
+    Block(Vec<WithSpan<Stmt<'src>>>),
+    Class(ClassDecl<'src>),
+    Expression(Box<WithSpan<Expr<'src>>>),
+    Function(Rc<FunctionDecl<'src>>),
+    If {
+        condition: Box<WithSpan<Expr<'src>>>,
+        then_branch: Box<WithSpan<Stmt<'src>>>,
+        else_branch: Option<Box<WithSpan<Stmt<'src>>>>,
+    },
+    Print(Box<WithSpan<Expr<'src>>>),
+    Return(Option<Box<WithSpan<Expr<'src>>>>),
+    Var {
+        name: WithSpan<Identifier>,
+        initializer: Option<Box<WithSpan<Expr<'src>>>>,
+    },
+    While {
+        condition: Box<WithSpan<Expr<'src>>>,
+        body: Box<WithSpan<Stmt<'src>>>,
+    },
+}
+
+/// Named function declaration. Kept behind an `Rc` in the AST so that runtime
+/// function values can share it without cloning the body.
+#[derive(Debug)]
+pub struct FunctionDecl<'src> {
+    pub name: WithSpan<Identifier>,
+    pub params: Vec<WithSpan<Identifier>>,
+    pub body: Vec<WithSpan<Stmt<'src>>>,
+}
+
+#[derive(Debug)]
+pub struct ClassDecl<'src> {
+    pub name: WithSpan<Identifier>,
+    /// Always an `Expr::Variable`, kept as an expression so the resolver can
+    /// bind it like any other variable use.
+    pub superclass: Option<WithSpan<Expr<'src>>>,
+    pub methods: Vec<Rc<FunctionDecl<'src>>>,
+}
+
+/// Parses a whole program, i.e. a list of declarations terminated by Eof.
+///
+/// Parsing carries on past syntax errors so that every independent error is
+/// recorded in the parser's diagnostics, but the program is only returned if
+/// there were none.
+pub fn parse<'a>(parser: &mut Parser<'a>) -> Result<Vec<WithSpan<Stmt<'a>>>, ()> {
+    let mut statements = Vec::new();
+    while !parser.matches(Token::Eof) {
+        if let Some(statement) = declaration(parser) {
+            statements.push(statement);
+        }
+    }
+    if parser.has_errors() {
+        return Err(());
+    }
+    Ok(statements)
+}
+
+/// Parses a declaration, recovering to the next statement boundary on error.
+fn declaration<'a>(parser: &mut Parser<'a>) -> Option<WithSpan<Stmt<'a>>> {
+    match try_declaration(parser) {
+        Ok(statement) => Some(statement),
+        Err(()) => {
+            parser.synchronize();
+            None
+        }
+    }
+}
+
+fn try_declaration<'a>(parser: &mut Parser<'a>) -> Result<WithSpan<Stmt<'a>>, ()> {
+    if parser.matches(Token::Class) {
+        return class_declaration(parser);
+    }
+    if parser.matches(Token::Fun) {
+        return fun_declaration(parser);
+    }
+    if parser.matches(Token::Var) {
+        return var_declaration(parser);
+    }
+    statement(parser)
+}
+
+fn class_declaration<'a>(parser: &mut Parser<'a>) -> Result<WithSpan<Stmt<'a>>, ()> {
+    let class = parser.advance();
+    let name = parser.expect_identifier("Expected class name.")?;
+    let superclass = if parser.matches(Token::Less) {
+        parser.advance();
+        let superclass = parser.expect_identifier("Expected superclass name.")?;
+        let span = superclass.span;
+        Some(WithSpan::new(Expr::Variable(superclass), span))
+    } else {
+        None
+    };
+    parser.expect(Token::LeftBrace, "Expected '{' before class body.")?;
+
+    let mut methods = Vec::new();
+    while !parser.matches(Token::RightBrace) && !parser.matches(Token::Eof) {
+        let (method, _) = function(parser, "method")?;
+        methods.push(Rc::new(method));
+    }
+    let right_brace = parser.expect(Token::RightBrace, "Expected '}' after class body.")?;
+    let span = Span::union(&class, &right_brace);
+    Ok(WithSpan::new(
+        Stmt::Class(ClassDecl {
+            name,
+            superclass,
+            methods,
+        }),
+        span,
+    ))
+}
+
+fn fun_declaration<'a>(parser: &mut Parser<'a>) -> Result<WithSpan<Stmt<'a>>, ()> {
+    let fun = parser.advance();
+    let (declaration, right_brace) = function(parser, "function")?;
+    let span = Span::union(&fun, &right_brace);
+    Ok(WithSpan::new(Stmt::Function(Rc::new(declaration)), span))
+}
+
+/// Parses the name, parameters and body shared by function and method
+/// declarations. `kind` is only used in diagnostics.
+fn function<'a>(
+    parser: &mut Parser<'a>,
+    kind: &str,
+) -> Result<(FunctionDecl<'a>, WithSpan<Token<'a>>), ()> {
+    let name = parser.expect_identifier(&format!("Expected {} name.", kind))?;
+    parser.expect(
+        Token::LeftParen,
+        &format!("Expected '(' after {} name.", kind),
+    )?;
+
+    let mut params = Vec::new();
+    if !parser.matches(Token::RightParen) {
+        loop {
+            // report the limit once and keep parsing the rest
+            if params.len() == 255 {
+                parser.error(
+                    Code::TooManyArguments,
+                    "Can't have more than 255 parameters.",
+                    parser.current_span(),
+                );
+            }
+            params.push(parser.expect_identifier("Expected parameter name.")?);
+            if !parser.matches(Token::Comma) {
+                break;
+            }
+            parser.advance();
+        }
+    }
+    parser.expect(Token::RightParen, "Expected ')' after parameters.")?;
+
+    let left_brace = parser.expect(
+        Token::LeftBrace,
+        &format!("Expected '{{' before {} body.", kind),
+    )?;
+    let (body, right_brace) = parser.nested(|parser| block_statements(parser, &left_brace))?;
+    Ok((FunctionDecl { name, params, body }, right_brace))
+}
+
+fn var_declaration<'a>(parser: &mut Parser<'a>) -> Result<WithSpan<Stmt<'a>>, ()> {
+    let var = parser.advance();
+    let name = parser.expect_identifier("Expected variable name.")?;
+    let initializer = if parser.matches(Token::Equal) {
+        parser.advance();
+        Some(Box::new(expr::parse(parser)?))
+    } else {
+        None
+    };
+    let semicolon = parser.expect(Token::Semicolon, "Expected ';' after variable declaration.")?;
+    let span = Span::union(&var, &semicolon);
+    Ok(WithSpan::new(Stmt::Var { name, initializer }, span))
+}
+
+fn statement<'a>(parser: &mut Parser<'a>) -> Result<WithSpan<Stmt<'a>>, ()> {
+    parser.nested(|parser| {
+        if parser.matches(Token::For) {
+            return for_statement(parser);
+        }
+        if parser.matches(Token::If) {
+            return if_statement(parser);
+        }
+        if parser.matches(Token::Print) {
+            return print_statement(parser);
+        }
+        if parser.matches(Token::Return) {
+            return return_statement(parser);
+        }
+        if parser.matches(Token::While) {
+            return while_statement(parser);
+        }
+        if parser.matches(Token::LeftBrace) {
+            return block(parser);
+        }
+        expression_statement(parser)
+    })
+}
+
+fn block<'a>(parser: &mut Parser<'a>) -> Result<WithSpan<Stmt<'a>>, ()> {
+    let left_brace = parser.advance();
+    let (statements, right_brace) = block_statements(parser, &left_brace)?;
+    let span = Span::union(&left_brace, &right_brace);
+    Ok(WithSpan::new(Stmt::Block(statements), span))
+}
+
+/// Parses the declarations of a block whose opening brace has already been
+/// consumed, returning them along with the closing brace.
+fn block_statements<'a>(
+    parser: &mut Parser<'a>,
+    left_brace: &WithSpan<Token>,
+) -> Result<(Vec<WithSpan<Stmt<'a>>>, WithSpan<Token<'a>>), ()> {
+    let mut statements = Vec::new();
+    while !parser.matches(Token::RightBrace) && !parser.matches(Token::Eof) {
+        if let Some(statement) = declaration(parser) {
+            statements.push(statement);
+        }
+    }
+    if !parser.matches(Token::RightBrace) {
+        parser.report(
+            Diagnostic::error("Expected '}' after block.", parser.current_span())
+                .with_code(Code::UnclosedDelimiter)
+                .with_label(left_brace.span, "unclosed `{` opened here"),
+        );
+        return Err(());
+    }
+    Ok((statements, parser.advance()))
+}
+
+/// Desugars `for (init; cond; incr) body` into the equivalent
+/// `{ init; while (cond) { body; incr; } }`.
+fn for_statement<'a>(parser: &mut Parser<'a>) -> Result<WithSpan<Stmt<'a>>, ()> {
+    let for_token = parser.advance();
+    parser.expect(Token::LeftParen, "Expected '(' after 'for'.")?;
+
+    let initializer = if parser.matches(Token::Semicolon) {
+        parser.advance();
+        None
+    } else if parser.matches(Token::Var) {
+        Some(var_declaration(parser)?)
+    } else {
+        Some(expression_statement(parser)?)
+    };
+
+    let condition = if parser.matches(Token::Semicolon) {
+        // a missing condition loops forever
+        WithSpan::new(
+            Expr::Literal(WithSpan::new(Token::True, for_token.span)),
+            for_token.span,
+        )
+    } else {
+        expr::parse(parser)?
+    };
+    parser.expect(Token::Semicolon, "Expected ';' after loop condition.")?;
+
+    let increment = if parser.matches(Token::RightParen) {
+        None
+    } else {
+        Some(expr::parse(parser)?)
+    };
+    parser.expect(Token::RightParen, "Expected ')' after for clauses.")?;
+
+    let mut body = statement(parser)?;
+    let span = Span::union(&for_token, &body);
+
+    if let Some(increment) = increment {
+        let increment_span = increment.span;
+        body = WithSpan::new(
+            Stmt::Block(vec![
+                body,
+                WithSpan::new(Stmt::Expression(Box::new(increment)), increment_span),
+            ]),
+            span,
+        );
+    }
+
+    body = WithSpan::new(
+        Stmt::While {
+            condition: Box::new(condition),
+            body: Box::new(body),
+        },
+        span,
+    );
+
+    if let Some(initializer) = initializer {
+        body = WithSpan::new(Stmt::Block(vec![initializer, body]), span);
+    }
+
+    Ok(body)
+}
+
+fn if_statement<'a>(parser: &mut Parser<'a>) -> Result<WithSpan<Stmt<'a>>, ()> {
+    let if_token = parser.advance();
+    parser.expect(Token::LeftParen, "Expected '(' after 'if'.")?;
+    let condition = expr::parse(parser)?;
+    parser.expect(Token::RightParen, "Expected ')' after if condition.")?;
+
+    let then_branch = statement(parser)?;
+    // an else is always bound to the nearest preceding if
+    let else_branch = if parser.matches(Token::Else) {
+        parser.advance();
+        Some(Box::new(statement(parser)?))
+    } else {
+        None
+    };
+
+    let end = else_branch.as_deref().unwrap_or(&then_branch);
+    let span = Span::union(&if_token, end);
+    Ok(WithSpan::new(
+        Stmt::If {
+            condition: Box::new(condition),
+            then_branch: Box::new(then_branch),
+            else_branch,
+        },
+        span,
+    ))
+}
+
+fn print_statement<'a>(parser: &mut Parser<'a>) -> Result<WithSpan<Stmt<'a>>, ()> {
+    let print = parser.advance();
+    let expr = expr::parse(parser)?;
+    let semicolon = parser.expect(Token::Semicolon, "Expected ';' after value.")?;
+    let span = Span::union(&print, &semicolon);
+    Ok(WithSpan::new(Stmt::Print(Box::new(expr)), span))
+}
+
+fn return_statement<'a>(parser: &mut Parser<'a>) -> Result<WithSpan<Stmt<'a>>, ()> {
+    let return_token = parser.advance();
+    let value = if parser.matches(Token::Semicolon) {
+        None
+    } else {
+        Some(Box::new(expr::parse(parser)?))
+    };
+    let semicolon = parser.expect(Token::Semicolon, "Expected ';' after return value.")?;
+    let span = Span::union(&return_token, &semicolon);
+    Ok(WithSpan::new(Stmt::Return(value), span))
+}
+
+fn while_statement<'a>(parser: &mut Parser<'a>) -> Result<WithSpan<Stmt<'a>>, ()> {
+    let while_token = parser.advance();
+    parser.expect(Token::LeftParen, "Expected '(' after 'while'.")?;
+    let condition = expr::parse(parser)?;
+    parser.expect(Token::RightParen, "Expected ')' after condition.")?;
+    let body = statement(parser)?;
+    let span = Span::union(&while_token, &body);
+    Ok(WithSpan::new(
+        Stmt::While {
+            condition: Box::new(condition),
+            body: Box::new(body),
+        },
+        span,
+    ))
+}
+
+fn expression_statement<'a>(parser: &mut Parser<'a>) -> Result<WithSpan<Stmt<'a>>, ()> {
+    let expr = expr::parse(parser)?;
+    let semicolon = parser.expect(Token::Semicolon, "Expected ';' after expression.")?;
+    let span = Span::union(&expr, &semicolon);
+    Ok(WithSpan::new(Stmt::Expression(Box::new(expr)), span))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{lexer::Scanner, parser::Parser};
+
+    fn parse_errors(source: &str) -> Vec<String> {
+        let tokens = Scanner::new(source).scan();
+        let mut parser = Parser::new(&tokens);
+        let _ = super::parse(&mut parser);
+        parser
+            .diagnostics()
+            .iter()
+            .map(|diag| diag.message.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_reports_every_independent_syntax_error() {
+        let errors = parse_errors(
+            "var = 1;\n\
+             print (1 + ;\n\
+             fun f() { var x = 1 print x; }\n\
+             print \"fine\";",
+        );
+
+        assert_eq!(
+            errors,
+            vec![
+                "Expected variable name.",
+                "Expected expression.",
+                "Expected ';' after variable declaration.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_too_many_parameters_and_arguments_do_not_stop_parsing() {
+        let names: Vec<String> = (0..300).map(|i| format!("p{}", i)).collect();
+        let errors = parse_errors(&format!(
+            "fun f({}) {{ print ; }}\n\
+             f({});\n\
+             print ;",
+            names.join(", "),
+            names.join(", ")
+        ));
+
+        assert_eq!(
+            errors,
+            vec![
+                "Can't have more than 255 parameters.",
+                "Expected expression.",
+                "Can't have more than 255 arguments.",
+                "Expected expression.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recovery_stops_at_end_of_input() {
+        assert_eq!(parse_errors("print"), vec!["Expected expression."]);
+        assert_eq!(
+            parse_errors("{ var a = 1;"),
+            vec!["Expected '}' after block."]
+        );
+    }
+
+    #[test]
+    fn test_nesting_is_limited() {
+        let nested = |depth: usize, open: &str, close: &str| {
+            format!("print {}1{};", open.repeat(depth), close.repeat(depth))
+        };
+        let errors = |source: &str, max_depth: usize| {
+            let tokens = Scanner::new(source).scan();
+            let mut parser = Parser::new(&tokens);
+            parser.set_max_depth(max_depth);
+            let _ = super::parse(&mut parser);
+            parser.diagnostics().len()
+        };
+
+        // the statement and the expression it prints take up two levels
+        assert_eq!(errors(&nested(8, "(", ")"), 10), 0);
+        assert_eq!(errors(&nested(9, "(", ")"), 10), 1);
+        assert_eq!(errors(&nested(9, "-", ""), 10), 1);
+        assert_eq!(
+            errors(&format!("{}{}", "{".repeat(20), "}".repeat(20)), 10),
+            1
+        );
+        assert_eq!(errors(&"{".repeat(20), 10), 1);
+
+        // chains are built in loops and have a budget of their own
+        let chain = |links: usize, link: &str| format!("print a{};", link.repeat(links));
+        assert_eq!(errors(&chain(300, " + a"), 10), 0);
+        assert_eq!(errors(&chain(300, " or a"), 10), 0);
+        assert_eq!(errors(&chain(300, ".b"), 10), 0);
+        assert_eq!(errors(&chain(300, "()"), 10), 0);
+    }
+}
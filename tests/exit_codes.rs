@@ -0,0 +1,226 @@
+use std::{env, fs, path::PathBuf, process::Command};
+
+/// Output of running the interpreter binary on a lox source file.
+struct Run {
+    status: i32,
+    stdout: String,
+    stderr: String,
+}
+
+/// Writes `source` to a scratch file named after the test and runs the
+/// interpreter `command` on it.
+fn roxi(name: &str, command: &str, source: &str) -> Run {
+    roxi_with_flags(name, &[], command, source)
+}
+
+fn roxi_with_flags(name: &str, flags: &[&str], command: &str, source: &str) -> Run {
+    let path: PathBuf = env::temp_dir().join(format!("roxi-{}-{}.lox", name, std::process::id()));
+    fs::write(&path, source).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_roxi"))
+        .args(flags)
+        .arg(command)
+        .arg(&path)
+        .output()
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+
+    Run {
+        status: output.status.code().unwrap(),
+        stdout: String::from_utf8(output.stdout).unwrap(),
+        stderr: String::from_utf8(output.stderr).unwrap(),
+    }
+}
+
+#[test]
+fn test_successful_commands_exit_zero() {
+    assert_eq!(roxi("ok-tokenize", "tokenize", "1 + 2").status, 0);
+    assert_eq!(roxi("ok-parse", "parse", "1 + 2").status, 0);
+
+    let evaluate = roxi("ok-evaluate", "evaluate", "1 + 2");
+    assert_eq!(evaluate.status, 0);
+    assert_eq!(evaluate.stdout, "3\n");
+
+    let run = roxi("ok-run", "run", "print \"hi\";");
+    assert_eq!(run.status, 0);
+    assert_eq!(run.stdout, "hi\n");
+}
+
+#[test]
+fn test_empty_file_still_tokenizes_eof() {
+    let run = roxi("empty-tokenize", "tokenize", "");
+
+    assert_eq!(run.status, 0);
+    assert_eq!(run.stdout, "EOF  null\n");
+}
+
+#[test]
+fn test_scan_errors_exit_65_and_still_print_valid_tokens() {
+    let run = roxi("scan-error", "tokenize", "( $ )");
+
+    assert_eq!(run.status, 65);
+    assert_eq!(
+        run.stdout,
+        "LEFT_PAREN ( null\nRIGHT_PAREN ) null\nEOF  null\n"
+    );
+    assert!(run.stderr.contains("Unexpected character: $"));
+
+    for command in ["parse", "evaluate", "run"] {
+        assert_eq!(roxi("scan-error", command, "\"unterminated").status, 65);
+    }
+}
+
+#[test]
+fn test_parse_errors_exit_65() {
+    assert_eq!(roxi("parse-error", "parse", "(1 +").status, 65);
+    assert_eq!(roxi("parse-error", "evaluate", "(1 +").status, 65);
+    assert_eq!(roxi("parse-error", "run", "print 1").status, 65);
+}
+
+#[test]
+fn test_resolve_errors_exit_65_without_running() {
+    let run = roxi("resolve-error", "run", "print 1; return 2;");
+
+    assert_eq!(run.status, 65);
+    assert_eq!(run.stdout, "");
+    assert!(run.stderr.contains("Can't return from top-level code."));
+}
+
+#[test]
+fn test_runtime_errors_exit_70() {
+    assert_eq!(roxi("runtime-error", "evaluate", "-\"a\"").status, 70);
+
+    let run = roxi("runtime-error", "run", "print 1; print nope;");
+    assert_eq!(run.status, 70);
+    assert_eq!(run.stdout, "1\n");
+    assert!(run.stderr.contains("Undefined variable 'nope'."));
+}
+
+#[test]
+fn test_runtime_errors_in_calls_print_stack_trace() {
+    let run = roxi(
+        "stack-trace",
+        "run",
+        "fun fib(n) {\n  return n - nil;\n}\nprint fib(1);\n",
+    );
+
+    assert_eq!(run.status, 70);
+    assert!(run
+        .stderr
+        .ends_with("[line 2] in fib\n[line 4] in script\n"));
+}
+
+#[test]
+fn test_error_format_flag() {
+    let json = roxi_with_flags(
+        "json",
+        &["--error-format=json"],
+        "run",
+        "print 1;\nprint (1 + ;",
+    );
+    assert_eq!(json.status, 65);
+    assert_eq!(json.stderr.lines().count(), 1);
+    assert!(json.stderr.starts_with("{\"file\":"));
+    assert!(json.stderr.contains(
+        "\"range\":{\"start\":{\"line\":2,\"column\":12},\"end\":{\"line\":2,\"column\":13}}"
+    ));
+
+    let short = roxi_with_flags("short", &["--error-format=short"], "run", "print nope;");
+    assert_eq!(short.status, 70);
+    assert!(short
+        .stderr
+        .ends_with(".lox:1:7: Error[E0301]: Undefined variable 'nope'.\n"));
+
+    let unknown = roxi_with_flags("unknown-format", &["--error-format=xml"], "run", "");
+    assert_eq!(unknown.status, 64);
+}
+
+#[test]
+fn test_warnings_only_fail_when_denied() {
+    let source = "fun f(unused) { return 1; print 2; }\nprint f(0);";
+
+    let warned = roxi("warnings", "run", source);
+    assert_eq!(warned.status, 0);
+    assert_eq!(warned.stdout, "1\n");
+    assert!(warned
+        .stderr
+        .contains("Warning[W0001]: Unused parameter 'unused'."));
+    assert!(warned.stderr.contains("Warning[W0002]: Unreachable code."));
+
+    let denied = roxi_with_flags("deny-warnings", &["--deny-warnings"], "run", source);
+    assert_eq!(denied.status, 65);
+    assert_eq!(denied.stdout, "");
+
+    let allowed = roxi_with_flags(
+        "allow-warnings",
+        &[
+            "--deny-warnings",
+            "--allow=unused-variable",
+            "--allow=unreachable-code",
+        ],
+        "run",
+        source,
+    );
+    assert_eq!(allowed.status, 0);
+    assert_eq!(allowed.stderr, "");
+
+    let unknown = roxi_with_flags("unknown-lint", &["--warn=everything"], "run", source);
+    assert_eq!(unknown.status, 64);
+}
+
+#[test]
+fn test_runaway_programs_are_stopped_with_diagnostics() {
+    let nested = format!("print {}1{};", "(".repeat(100_000), ")".repeat(100_000));
+    let parse = roxi("deep-nesting", "run", &nested);
+    assert_eq!(parse.status, 65);
+    assert!(parse.stderr.contains("Error[E0105]: Too much nesting."));
+
+    let parens = format!("{}1{}", "(".repeat(300_000), ")".repeat(300_000));
+    for command in ["parse", "run"] {
+        let source = match command {
+            "run" => format!("print {};", parens),
+            _ => parens.clone(),
+        };
+        let deep = roxi("deeper-nesting", command, &source);
+        assert_eq!(deep.status, 65);
+        assert!(deep.stderr.contains("Error[E0105]: Too much nesting."));
+    }
+
+    let chain = |terms: usize| format!("print {};", vec!["1"; terms].join(" + "));
+    let long = roxi("long-chain", "run", &chain(301));
+    assert_eq!(long.status, 0);
+    assert_eq!(long.stdout, "301\n");
+    let longer = roxi("longer-chain", "run", &chain(300_000));
+    assert_eq!(longer.status, 65);
+    assert!(longer.stderr.contains("Error[E0105]: Too much nesting."));
+
+    let nested = format!("print {}1{};", "(".repeat(200_000), ")".repeat(200_000));
+    let limited = roxi_with_flags("max-nesting", &["--max-nesting=16384"], "run", &nested);
+    assert_eq!(limited.status, 65);
+    assert!(limited.stderr.contains("Error[E0105]: Too much nesting."));
+    let unlimited = roxi_with_flags(
+        "max-nesting-too-large",
+        &["--max-nesting=10000000"],
+        "run",
+        &nested,
+    );
+    assert_eq!(unlimited.status, 64);
+
+    let recursion = roxi(
+        "deep-recursion",
+        "run",
+        "fun f(n) { return f(n + 1); }\nf(0);",
+    );
+    assert_eq!(recursion.status, 70);
+    assert!(recursion.stderr.contains("Error[E0309]: Stack overflow."));
+
+    let looping = roxi_with_flags(
+        "step-limit",
+        &["--max-steps=10000"],
+        "run",
+        "while (true) {}",
+    );
+    assert_eq!(looping.status, 70);
+    assert!(looping
+        .stderr
+        .contains("Error[E0310]: Execution step limit exceeded."));
+}